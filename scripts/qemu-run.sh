@@ -0,0 +1,32 @@
+#!/bin/sh
+# Cargo runner, boots the kernel image given as the first argument in QEMU.
+#
+# Test kernels, which cargo builds under deps/, run headless with the serial
+# port on stdio and the isa-debug-exit device. They exit QEMU with
+# (0x10 << 1) | 1 when every test passed, which is turned into 0 here. A
+# hung test kernel is killed after QEMU_TIMEOUT seconds.
+
+kernel="$1"
+shift
+
+case "$kernel" in
+*/deps/*)
+    timeout "${QEMU_TIMEOUT:-300}" qemu-system-x86_64 \
+        -kernel "$kernel" \
+        -device isa-debug-exit,iobase=0xf4,iosize=0x04 \
+        -serial stdio \
+        -display none \
+        -no-reboot \
+        "$@"
+    status=$?
+    if [ "$status" -eq 33 ]; then
+        exit 0
+    fi
+    echo "qemu exited with $status" >&2
+    exit 1
+    ;;
+*)
+    exec qemu-system-x86_64 -no-reboot -no-shutdown -d mmu,unimp,int,guest_errors \
+        -kernel "$kernel" "$@"
+    ;;
+esac
@@ -0,0 +1,215 @@
+// Just enough ACPI to find the interrupt controllers.
+//
+// See: ACPI spec 6.5, 5.2 "ACPI System Description Tables"
+//
+// The RSDP is found by scanning the BIOS areas in low memory. It points at
+// the RSDT, a list of physical pointers to the other tables, one of which is
+// the MADT ("APIC" signature) describing the local APICs, I/O APICs and how
+// ISA IRQs are wired to them. Only the 32-bit RSDT is used, XSDT entries
+// could point above 4 GiB, which this kernel can't map anyway.
+
+use crate::paging::{self, PageFlags, phys_to_virt};
+
+use alloc::vec::Vec;
+
+const RSDP_SIGNATURE: &[u8; 8] = b"RSD PTR ";
+const MADT_SIGNATURE: &[u8; 4] = b"APIC";
+
+// the EBDA segment is stored in the BIOS data area
+const EBDA_POINTER: usize = 0x40e;
+const BIOS_AREA: core::ops::Range<usize> = 0xe_0000..0x10_0000;
+
+#[repr(C, packed)]
+struct Rsdp {
+    signature: [u8; 8],
+    checksum: u8,
+    oem_id: [u8; 6],
+    revision: u8,
+    rsdt_address: u32,
+}
+
+#[repr(C, packed)]
+struct SdtHeader {
+    signature: [u8; 4],
+    length: u32,
+    revision: u8,
+    checksum: u8,
+    oem_id: [u8; 6],
+    oem_table_id: [u8; 8],
+    oem_revision: u32,
+    creator_id: u32,
+    creator_revision: u32,
+}
+
+#[repr(C, packed)]
+struct MadtHeader {
+    header: SdtHeader,
+    local_apic_address: u32,
+    flags: u32,
+}
+
+// MADT flag: the system also has a pair of 8259 PICs
+const PCAT_COMPAT: u32 = 1 << 0;
+
+// MADT entry types
+const LOCAL_APIC: u8 = 0;
+const IO_APIC: u8 = 1;
+const SOURCE_OVERRIDE: u8 = 2;
+const LOCAL_APIC_NMI: u8 = 4;
+
+/// An I/O APIC and the first global system interrupt it handles.
+#[derive(Clone, Copy, Debug)]
+pub struct IoApicInfo {
+    pub id: u8,
+    pub address: usize,
+    pub gsi_base: u32,
+}
+
+/// An ISA IRQ that is not connected to the I/O APIC pin of the same number,
+/// or not with the ISA default of active high, edge triggered.
+#[derive(Clone, Copy, Debug)]
+pub struct SourceOverride {
+    pub irq: u8,
+    pub gsi: u32,
+    pub flags: u16, // MPS INTI flags: polarity in bits 0-1, trigger mode in 2-3
+}
+
+/// A local APIC LINT pin that is connected to NMI.
+#[derive(Clone, Copy, Debug)]
+pub struct LocalApicNmi {
+    pub processor: u8, // 0xff means all processors
+    pub flags: u16,
+    pub lint: u8,
+}
+
+/// What the MADT says about the interrupt controllers.
+#[derive(Debug)]
+pub struct Madt {
+    pub local_apic_address: usize,
+    pub legacy_pics: bool,
+    pub local_apic_ids: Vec<u8>,
+    pub io_apics: Vec<IoApicInfo>,
+    pub overrides: Vec<SourceOverride>,
+    pub nmis: Vec<LocalApicNmi>,
+}
+
+fn checksum_ok(addr: usize, len: usize) -> bool {
+    let bytes = unsafe { core::slice::from_raw_parts(addr as *const u8, len) };
+    bytes.iter().fold(0u8, |sum, &b| sum.wrapping_add(b)) == 0
+}
+
+fn scan_for_rsdp(range: core::ops::Range<usize>) -> Option<usize> {
+    range.step_by(16).map(phys_to_virt).find(|&addr| {
+        let signature = unsafe { &*(addr as *const [u8; 8]) };
+        signature == RSDP_SIGNATURE && checksum_ok(addr, size_of::<Rsdp>())
+    })
+}
+
+fn find_rsdp() -> Option<usize> {
+    let ebda = unsafe { (phys_to_virt(EBDA_POINTER) as *const u16).read() } as usize * 16;
+    let in_ebda = (ebda != 0)
+        .then(|| scan_for_rsdp(ebda..ebda + 1024))
+        .flatten();
+
+    in_ebda.or_else(|| scan_for_rsdp(BIOS_AREA))
+}
+
+// FRAMES only for the mapping itself, parsing allocates and the heap takes
+// it to grow
+fn map(phys: usize, len: usize) -> Result<usize, &'static str> {
+    paging::map_physical(phys, len, PageFlags::empty(), &mut crate::FRAMES.lock())
+}
+
+/// Read the header of the table at `phys` without keeping it mapped.
+fn peek_header<R>(phys: usize, f: impl FnOnce(&SdtHeader) -> R) -> Result<R, &'static str> {
+    let header = map(phys, size_of::<SdtHeader>())?;
+    let result = f(unsafe { &*(header as *const SdtHeader) });
+    // the latest mapping, so the window space is reused by the next one
+    unsafe { paging::unmap_physical(header, size_of::<SdtHeader>()) };
+    Ok(result)
+}
+
+/// Map the table at `phys` and check it, returning its virtual address.
+fn map_table(phys: usize) -> Result<usize, &'static str> {
+    let len = peek_header(phys, |header| header.length)? as usize;
+
+    let table = map(phys, len)?;
+    if !checksum_ok(table, len) {
+        return Err("ACPI table checksum mismatch");
+    }
+
+    Ok(table)
+}
+
+fn find_table(signature: &[u8; 4]) -> Result<usize, &'static str> {
+    let rsdp = find_rsdp().ok_or("no ACPI RSDP")?;
+    let rsdt_phys = unsafe { (*(rsdp as *const Rsdp)).rsdt_address } as usize;
+    let rsdt = map_table(rsdt_phys)?;
+
+    let len = unsafe { (*(rsdt as *const SdtHeader)).length } as usize;
+    let entries = (len - size_of::<SdtHeader>()) / 4;
+    let first = (rsdt + size_of::<SdtHeader>()) as *const u32;
+
+    for i in 0..entries {
+        let phys = unsafe { first.add(i).read_unaligned() } as usize;
+        if peek_header(phys, |header| &header.signature == signature)? {
+            return map_table(phys);
+        }
+    }
+
+    Err("ACPI table not found")
+}
+
+/// Find and parse the MADT.
+pub fn madt() -> Result<Madt, &'static str> {
+    let table = find_table(MADT_SIGNATURE)?;
+    let header = unsafe { &*(table as *const MadtHeader) };
+
+    let mut madt = Madt {
+        local_apic_address: header.local_apic_address as usize,
+        legacy_pics: header.flags & PCAT_COMPAT != 0,
+        local_apic_ids: Vec::new(),
+        io_apics: Vec::new(),
+        overrides: Vec::new(),
+        nmis: Vec::new(),
+    };
+
+    // variable length entries, each starting with its type and length
+    let end = table + header.header.length as usize;
+    let mut entry = table + size_of::<MadtHeader>();
+    while entry + 2 <= end {
+        let bytes = |offset: usize| unsafe { ((entry + offset) as *const u8).read() };
+        let word = |offset: usize| unsafe { ((entry + offset) as *const u16).read_unaligned() };
+        let dword = |offset: usize| unsafe { ((entry + offset) as *const u32).read_unaligned() };
+
+        let len = bytes(1) as usize;
+        if len < 2 || entry + len > end {
+            return Err("malformed MADT entry");
+        }
+
+        match bytes(0) {
+            // only processors that are enabled
+            LOCAL_APIC if dword(4) & 1 != 0 => madt.local_apic_ids.push(bytes(3)),
+            IO_APIC => madt.io_apics.push(IoApicInfo {
+                id: bytes(2),
+                address: dword(4) as usize,
+                gsi_base: dword(8),
+            }),
+            SOURCE_OVERRIDE => madt.overrides.push(SourceOverride {
+                irq: bytes(3),
+                gsi: dword(4),
+                flags: word(8),
+            }),
+            LOCAL_APIC_NMI => madt.nmis.push(LocalApicNmi {
+                processor: bytes(2),
+                flags: word(3),
+                lint: bytes(5),
+            }),
+            _ => {}
+        }
+
+        entry += len;
+    }
+
+    Ok(madt)
+}
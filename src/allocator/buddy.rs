@@ -0,0 +1,289 @@
+// Buddy allocator for physically contiguous blocks of 2^order pages.
+//
+// At boot it claims a few contiguous runs of usable memory from the frame
+// allocator (which was itself filled from the multiboot memory map), one of
+// them below 16 MiB so ISA DMA requests can be served. The runs are mapped
+// back to back in a dedicated virtual window, so drivers get both the
+// physical address to program into the device and a pointer to use.
+//
+// A free block of order `k` at physical address `p` has its buddy at
+// `p ^ (PAGE_SIZE << k)`. Blocks are kept in one singly linked list per
+// order; the node lives in the free block itself and stores the physical
+// address of the next free block.
+
+use crate::frame::{BitmapFrameAllocator, FRAME_SIZE};
+use crate::paging::{self, PAGE_SIZE, PageFlags};
+use crate::println;
+
+/// Largest block is 2^MAX_ORDER pages (4 MiB)
+pub const MAX_ORDER: usize = 10;
+
+/// Highest physical address ISA DMA controllers can reach
+pub const ISA_DMA_LIMIT: usize = 16 * 1024 * 1024;
+
+const WINDOW_START: usize = 0xe000_0000;
+const MAX_REGIONS: usize = 4;
+
+// frames claimed from the frame allocator at boot
+const DMA_POOL_FRAMES: usize = 1024; // 4 MiB
+const NORMAL_POOL_FRAMES: usize = 4096; // 16 MiB
+
+const NONE: usize = usize::MAX;
+
+/// Where a block may come from.
+#[allow(dead_code)]
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Zone {
+    /// Entirely below `ISA_DMA_LIMIT`
+    Dma,
+    /// Anywhere, preferring memory above `ISA_DMA_LIMIT`
+    Normal,
+}
+
+/// A block handed out by the buddy allocator.
+#[allow(dead_code)]
+#[derive(Clone, Copy)]
+pub struct PageBlock {
+    pub phys: usize,
+    pub virt: usize,
+    pub order: usize,
+}
+
+#[allow(dead_code)]
+impl PageBlock {
+    pub fn size(&self) -> usize {
+        PAGE_SIZE << self.order
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Region {
+    phys: usize,
+    virt: usize,
+    size: usize,
+}
+
+impl Region {
+    fn contains(&self, phys: usize, size: usize) -> bool {
+        phys >= self.phys && phys + size <= self.phys + self.size
+    }
+}
+
+pub struct BuddyAllocator {
+    regions: [Region; MAX_REGIONS],
+    region_count: usize,
+    next_virt: usize,
+    free_lists: [usize; MAX_ORDER + 1],
+}
+
+#[allow(dead_code)]
+impl BuddyAllocator {
+    pub const fn new() -> Self {
+        BuddyAllocator {
+            regions: [Region {
+                phys: 0,
+                virt: 0,
+                size: 0,
+            }; MAX_REGIONS],
+            region_count: 0,
+            next_virt: WINDOW_START,
+            free_lists: [NONE; MAX_ORDER + 1],
+        }
+    }
+
+    /// Claim the DMA and normal pools from `frames`.
+    ///
+    /// When memory is tight the pools are shrunk until they fit.
+    pub fn init(&mut self, frames: &mut BitmapFrameAllocator) {
+        for (want, limit) in [
+            (DMA_POOL_FRAMES, ISA_DMA_LIMIT),
+            (NORMAL_POOL_FRAMES, usize::MAX),
+        ] {
+            let mut count = want;
+            while count > 0 {
+                if let Some(phys) = frames.allocate_contiguous_below(count, limit) {
+                    self.add_region(phys, count * FRAME_SIZE, frames);
+                    break;
+                }
+                count /= 2;
+            }
+        }
+    }
+
+    /// Map `[phys, phys + size)` into the window and split it into the
+    /// largest aligned blocks that fit.
+    fn add_region(&mut self, phys: usize, size: usize, frames: &mut BitmapFrameAllocator) {
+        assert!(self.region_count < MAX_REGIONS, "too many buddy regions");
+
+        let region = Region {
+            phys,
+            virt: self.next_virt,
+            size,
+        };
+
+        for offset in (0..size).step_by(PAGE_SIZE) {
+            unsafe {
+                paging::map(
+                    region.virt + offset,
+                    phys + offset,
+                    PageFlags::WRITABLE,
+                    frames,
+                )
+                .expect("map buddy region");
+            }
+        }
+
+        self.regions[self.region_count] = region;
+        self.region_count += 1;
+        self.next_virt += size;
+
+        println!(
+            "buddy: {:#x}..{:#x} mapped at {:#x}",
+            phys,
+            phys + size,
+            region.virt
+        );
+
+        let mut block = phys;
+        while block < phys + size {
+            let mut order = MAX_ORDER;
+            while block % (PAGE_SIZE << order) != 0 || block + (PAGE_SIZE << order) > phys + size {
+                order -= 1;
+            }
+
+            self.push(block, order);
+            block += PAGE_SIZE << order;
+        }
+    }
+
+    fn region_of(&self, phys: usize, size: usize) -> Option<&Region> {
+        self.regions[..self.region_count]
+            .iter()
+            .find(|r| r.contains(phys, size))
+    }
+
+    fn virt(&self, phys: usize) -> usize {
+        let region = self
+            .region_of(phys, PAGE_SIZE)
+            .expect("block in buddy region");
+        region.virt + (phys - region.phys)
+    }
+
+    // the `next` pointer stored at the start of every free block
+    fn next_of(&self, phys: usize) -> *mut usize {
+        self.virt(phys) as *mut usize
+    }
+
+    fn push(&mut self, phys: usize, order: usize) {
+        unsafe { self.next_of(phys).write(self.free_lists[order]) };
+        self.free_lists[order] = phys;
+    }
+
+    /// Unlink the first block of `order` that satisfies `accept`.
+    fn take(&mut self, order: usize, accept: impl Fn(usize) -> bool) -> Option<usize> {
+        let mut prev = NONE;
+        let mut block = self.free_lists[order];
+
+        while block != NONE {
+            let next = unsafe { self.next_of(block).read() };
+            if accept(block) {
+                if prev == NONE {
+                    self.free_lists[order] = next;
+                } else {
+                    unsafe { self.next_of(prev).write(next) };
+                }
+                return Some(block);
+            }
+
+            prev = block;
+            block = next;
+        }
+
+        None
+    }
+
+    /// Allocate a block of `order`, splitting a bigger one if needed. Only
+    /// blocks for which `accept(phys, size)` holds are considered.
+    fn alloc_matching(
+        &mut self,
+        order: usize,
+        accept: impl Fn(usize, usize) -> bool,
+    ) -> Option<usize> {
+        for k in order..=MAX_ORDER {
+            let size = PAGE_SIZE << k;
+            let Some(block) = self.take(k, |b| accept(b, size)) else {
+                continue;
+            };
+
+            // hand back the upper halves until the block is the right size
+            for split in (order..k).rev() {
+                self.push(block + (PAGE_SIZE << split), split);
+            }
+
+            return Some(block);
+        }
+
+        None
+    }
+
+    /// Allocate `2^order` physically contiguous pages from `zone`.
+    pub fn alloc_pages(&mut self, order: usize, zone: Zone) -> Option<PageBlock> {
+        if order > MAX_ORDER {
+            return None;
+        }
+
+        let phys = match zone {
+            Zone::Dma => self.alloc_matching(order, |b, size| b + size <= ISA_DMA_LIMIT)?,
+            // keep the scarce low memory for requests that need it
+            Zone::Normal => self
+                .alloc_matching(order, |b, _| b >= ISA_DMA_LIMIT)
+                .or_else(|| self.alloc_matching(order, |_, _| true))?,
+        };
+
+        Some(PageBlock {
+            phys,
+            virt: self.virt(phys),
+            order,
+        })
+    }
+
+    /// Return a block from `alloc_pages`, merging it with its buddy for as
+    /// long as the buddy is free too.
+    pub fn free_pages(&mut self, block: PageBlock) {
+        let mut phys = block.phys;
+        let mut order = block.order;
+        let region = *self
+            .region_of(phys, PAGE_SIZE << order)
+            .expect("freed block belongs to the buddy allocator");
+
+        while order < MAX_ORDER {
+            let buddy = phys ^ (PAGE_SIZE << order);
+            if !region.contains(buddy, PAGE_SIZE << order) {
+                break;
+            }
+
+            if self.take(order, |b| b == buddy).is_none() {
+                break;
+            }
+
+            phys = phys.min(buddy);
+            order += 1;
+        }
+
+        self.push(phys, order);
+    }
+
+    /// Number of free blocks of each order.
+    pub fn free_blocks(&self) -> [usize; MAX_ORDER + 1] {
+        let mut counts = [0; MAX_ORDER + 1];
+        for (order, count) in counts.iter_mut().enumerate() {
+            let mut block = self.free_lists[order];
+            while block != NONE {
+                *count += 1;
+                block = unsafe { self.next_of(block).read() };
+            }
+        }
+
+        counts
+    }
+}
@@ -0,0 +1,73 @@
+use super::{FreeSpace, HeapBackend, align_up};
+use core::alloc::Layout;
+use core::ptr::null_mut;
+
+pub struct BumpAlloc {
+    start: usize,
+    end: usize,
+    next: usize,
+    allocs: usize,
+}
+
+impl BumpAlloc {
+    pub const fn new() -> Self {
+        BumpAlloc {
+            start: 0,
+            end: 0,
+            next: 0,
+            allocs: 0,
+        }
+    }
+}
+
+impl HeapBackend for BumpAlloc {
+    unsafe fn init(&mut self, start: usize, size: usize) {
+        self.start = start;
+        self.end = start + size;
+        self.next = start;
+        self.allocs = 0;
+    }
+
+    unsafe fn extend(&mut self, start: usize, size: usize) {
+        assert_eq!(start, self.end, "bump heap can only grow at its end");
+        self.end += size;
+    }
+
+    fn free_tail(&self, _top: usize) -> usize {
+        self.next
+    }
+
+    unsafe fn release_tail(&mut self, from: usize) {
+        self.end = from;
+    }
+
+    fn free_space(&self) -> FreeSpace {
+        FreeSpace {
+            total: self.end - self.next,
+            largest: self.end - self.next,
+        }
+    }
+
+    unsafe fn alloc(&mut self, layout: Layout) -> *mut u8 {
+        let alloc_start = align_up(self.next, layout.align());
+        let alloc_end = alloc_start + layout.size();
+
+        if alloc_end > self.end {
+            return null_mut();
+        }
+
+        self.next = alloc_end;
+        self.allocs += 1;
+
+        alloc_start as *mut u8
+    }
+
+    /// deallocs the whole thing - it a bump allocator
+    unsafe fn dealloc(&mut self, _ptr: *mut u8, _layout: Layout) {
+        self.allocs -= 1;
+
+        if self.allocs == 0 {
+            self.next = self.start;
+        }
+    }
+}
@@ -0,0 +1,140 @@
+// Heap that maps memory on demand.
+//
+// The whole of [HEAP_START, HEAP_END) is reserved for the heap, but only the
+// part below `top` is backed by frames. When the inner heap runs dry, more
+// frames are mapped at `top` and handed to it; when enough memory at the
+// very end of the heap is free again, those pages are unmapped and their
+// frames returned.
+
+use super::{FreeSpace, HEAP_END, HEAP_START, HeapBackend, align_up};
+use crate::paging::{self, PAGE_SIZE, PageFlags};
+use crate::println;
+use core::alloc::Layout;
+
+// grow by at least this much so small allocations don't map a page each
+const GROW_MIN: usize = 16 * PAGE_SIZE;
+
+// only give memory back once this much is free at the end of the heap
+const SHRINK_THRESHOLD: usize = 64 * PAGE_SIZE;
+
+pub struct GrowableHeap<A: HeapBackend> {
+    inner: A,
+    base_top: usize, // never shrink below the initial mapping
+    top: usize,
+}
+
+impl<A: HeapBackend> GrowableHeap<A> {
+    pub const fn new(inner: A) -> Self {
+        GrowableHeap {
+            inner,
+            base_top: 0,
+            top: 0,
+        }
+    }
+
+    /// Map enough new pages at `top` to fit `layout` and give them to the
+    /// inner heap.
+    fn grow(&mut self, layout: Layout) -> bool {
+        let size = align_up(layout.size() + layout.align(), PAGE_SIZE).max(GROW_MIN);
+        if size > HEAP_END - self.top {
+            return false;
+        }
+
+        let mut frames = crate::FRAMES.lock();
+        let start = self.top;
+        for page in (start..start + size).step_by(PAGE_SIZE) {
+            let mapped = frames.allocate().is_some_and(|frame| {
+                let ok = unsafe { paging::map(page, frame, PageFlags::WRITABLE, &mut frames) };
+                if ok.is_err() {
+                    frames.deallocate(frame);
+                }
+                ok.is_ok()
+            });
+
+            if !mapped {
+                // out of frames for the page or its table, undo the
+                // partial growth
+                for page in (start..page).step_by(PAGE_SIZE) {
+                    if let Some(frame) = unsafe { paging::unmap(page) } {
+                        frames.deallocate(frame);
+                    }
+                }
+                return false;
+            }
+        }
+
+        self.top += size;
+        unsafe { self.inner.extend(start, size) };
+        true
+    }
+
+    /// Unmap the free pages at the end of the heap, if there are enough.
+    fn shrink(&mut self) {
+        let cut = align_up(self.inner.free_tail(self.top), PAGE_SIZE).max(self.base_top);
+        if self.top - cut < SHRINK_THRESHOLD {
+            return;
+        }
+
+        unsafe { self.inner.release_tail(cut) };
+
+        let mut frames = crate::FRAMES.lock();
+        for page in (cut..self.top).step_by(PAGE_SIZE) {
+            if let Some(frame) = unsafe { paging::unmap(page) } {
+                frames.deallocate(frame);
+            }
+        }
+        self.top = cut;
+    }
+}
+
+impl<A: HeapBackend> HeapBackend for GrowableHeap<A> {
+    unsafe fn init(&mut self, start: usize, size: usize) {
+        self.base_top = start + size;
+        self.top = start + size;
+        unsafe { self.inner.init(start, size) };
+    }
+
+    unsafe fn extend(&mut self, start: usize, size: usize) {
+        self.top = start + size;
+        unsafe { self.inner.extend(start, size) };
+    }
+
+    fn free_tail(&self, top: usize) -> usize {
+        self.inner.free_tail(top)
+    }
+
+    unsafe fn release_tail(&mut self, from: usize) {
+        unsafe { self.inner.release_tail(from) };
+    }
+
+    fn free_space(&self) -> FreeSpace {
+        self.inner.free_space()
+    }
+
+    unsafe fn alloc(&mut self, layout: Layout) -> *mut u8 {
+        let ptr = unsafe { self.inner.alloc(layout) };
+        if !ptr.is_null() || !self.grow(layout) {
+            return ptr;
+        }
+
+        unsafe { self.inner.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&mut self, ptr: *mut u8, layout: Layout) {
+        unsafe { self.inner.dealloc(ptr, layout) };
+
+        // finding the free tail walks the inner heap, skip that when the
+        // block is too far below top to leave enough free at the end
+        if ptr as usize + layout.size() + SHRINK_THRESHOLD >= self.top {
+            self.shrink();
+        }
+    }
+
+    fn print_stats(&self) {
+        println!(
+            "heap: {:#x}..{:#x} mapped out of {:#x}..{:#x}",
+            HEAP_START, self.top, HEAP_START, HEAP_END
+        );
+        self.inner.print_stats();
+    }
+}
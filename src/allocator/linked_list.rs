@@ -0,0 +1,185 @@
+// First-fit free list allocator.
+//
+// Free blocks are kept in a singly linked list sorted by address, with the
+// list node stored inside the free memory itself. Keeping the list sorted
+// means a freed block only has to look at its two neighbours to coalesce.
+//
+// Every block start and size is a multiple of BLOCK_ALIGN, which is also
+// the size of a list node. That way the padding in front of an aligned
+// allocation and the space left after it are always either empty or big
+// enough to hold a node of their own.
+
+use super::{FreeSpace, HeapBackend, align_up};
+use core::alloc::Layout;
+use core::ptr::null_mut;
+
+struct FreeBlock {
+    size: usize,
+    next: *mut FreeBlock,
+}
+
+const BLOCK_ALIGN: usize = size_of::<FreeBlock>();
+
+pub struct LinkedListAlloc {
+    head: *mut FreeBlock,
+}
+
+// the list only points into the heap region the allocator owns
+unsafe impl Send for LinkedListAlloc {}
+
+impl LinkedListAlloc {
+    pub const fn new() -> Self {
+        LinkedListAlloc { head: null_mut() }
+    }
+
+    /// Size and alignment of the block actually handed out for `layout`.
+    fn block_layout(layout: Layout) -> (usize, usize) {
+        let size = align_up(layout.size().max(BLOCK_ALIGN), BLOCK_ALIGN);
+        let align = layout.align().max(BLOCK_ALIGN);
+        (size, align)
+    }
+
+    /// Put `[addr, addr + size)` back into the list, merging it with
+    /// neighbouring free blocks.
+    unsafe fn insert(&mut self, addr: usize, size: usize) {
+        let mut prev: *mut FreeBlock = null_mut();
+        let mut next = self.head;
+
+        unsafe {
+            while !next.is_null() && (next as usize) < addr {
+                prev = next;
+                next = (*next).next;
+            }
+
+            let block = addr as *mut FreeBlock;
+            block.write(FreeBlock { size, next });
+
+            if !next.is_null() && addr + size == next as usize {
+                (*block).size += (*next).size;
+                (*block).next = (*next).next;
+            }
+
+            if prev.is_null() {
+                self.head = block;
+            } else if prev as usize + (*prev).size == addr {
+                (*prev).size += (*block).size;
+                (*prev).next = (*block).next;
+            } else {
+                (*prev).next = block;
+            }
+        }
+    }
+}
+
+impl HeapBackend for LinkedListAlloc {
+    unsafe fn init(&mut self, start: usize, size: usize) {
+        let aligned = align_up(start, BLOCK_ALIGN);
+        let size = (size - (aligned - start)) & !(BLOCK_ALIGN - 1);
+
+        unsafe { self.insert(aligned, size) };
+    }
+
+    unsafe fn extend(&mut self, start: usize, size: usize) {
+        unsafe { self.init(start, size) };
+    }
+
+    fn free_tail(&self, top: usize) -> usize {
+        let mut block = self.head;
+        unsafe {
+            while !block.is_null() {
+                if block as usize + (*block).size == top {
+                    return block as usize;
+                }
+                block = (*block).next;
+            }
+        }
+
+        top
+    }
+
+    unsafe fn release_tail(&mut self, from: usize) {
+        let mut link: *mut *mut FreeBlock = &mut self.head;
+
+        unsafe {
+            // the free tail is the last block in the list
+            while !(*link).is_null() && !(**link).next.is_null() {
+                link = &mut (**link).next;
+            }
+
+            let block = *link;
+            if block.is_null() || (block as usize) > from {
+                return;
+            }
+
+            if block as usize == from {
+                *link = null_mut();
+            } else {
+                (*block).size = from - block as usize;
+            }
+        }
+    }
+
+    fn free_space(&self) -> FreeSpace {
+        let mut free = FreeSpace::default();
+        let mut block = self.head;
+        unsafe {
+            while !block.is_null() {
+                free.total += (*block).size;
+                free.largest = free.largest.max((*block).size);
+                block = (*block).next;
+            }
+        }
+
+        free
+    }
+
+    unsafe fn alloc(&mut self, layout: Layout) -> *mut u8 {
+        let (size, align) = Self::block_layout(layout);
+
+        // `link` is the pointer that refers to `block`, either the list head
+        // or the `next` field of the previous block
+        let mut link: *mut *mut FreeBlock = &mut self.head;
+
+        unsafe {
+            while !(*link).is_null() {
+                let block = *link;
+                let block_start = block as usize;
+                let block_end = block_start + (*block).size;
+
+                let alloc_start = align_up(block_start, align);
+                let alloc_end = alloc_start + size;
+                if alloc_end > block_end {
+                    link = &mut (*block).next;
+                    continue;
+                }
+
+                // splice whatever is left on either side back in place
+                let mut rest = (*block).next;
+                if alloc_end < block_end {
+                    let tail = alloc_end as *mut FreeBlock;
+                    tail.write(FreeBlock {
+                        size: block_end - alloc_end,
+                        next: rest,
+                    });
+                    rest = tail;
+                }
+
+                if alloc_start > block_start {
+                    (*block).size = alloc_start - block_start;
+                    (*block).next = rest;
+                    rest = block;
+                }
+
+                *link = rest;
+                return alloc_start as *mut u8;
+            }
+        }
+
+        null_mut()
+    }
+
+    unsafe fn dealloc(&mut self, ptr: *mut u8, layout: Layout) {
+        let (size, _) = Self::block_layout(layout);
+        unsafe { self.insert(ptr as usize, size) };
+    }
+}
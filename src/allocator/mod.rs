@@ -0,0 +1,241 @@
+use crate::frame::FRAME_SIZE;
+use crate::paging::{self, PageFlags};
+use crate::{println, utils::mutex::SpinMutex};
+use core::alloc::{GlobalAlloc, Layout};
+
+pub mod buddy;
+pub mod growable;
+
+// only one of the heaps is in use depending on the bump-alloc feature
+#[cfg_attr(not(feature = "bump-alloc"), allow(dead_code))]
+pub mod bump;
+#[cfg_attr(feature = "bump-alloc", allow(dead_code))]
+pub mod linked_list;
+#[cfg_attr(feature = "bump-alloc", allow(dead_code))]
+pub mod slab;
+pub mod stats;
+
+#[cfg(feature = "heap-redzone")]
+pub mod redzone;
+
+/// Virtual address range reserved for the kernel heap
+pub const HEAP_START: usize = 0xd000_0000;
+pub const HEAP_END: usize = 0xe000_0000;
+
+/// Number of frames mapped for the kernel heap at boot, it grows from there
+const HEAP_FRAMES: usize = 256; // 1 MiB
+
+/// Heap implementation backing the global allocator.
+///
+/// The bump allocator never reuses memory, but is simple enough to rule out
+/// the heap when debugging early boot.
+#[cfg(feature = "bump-alloc")]
+pub type KernelHeap = stats::TrackingHeap<Hardened<growable::GrowableHeap<bump::BumpAlloc>>>;
+#[cfg(not(feature = "bump-alloc"))]
+pub type KernelHeap = stats::TrackingHeap<
+    Hardened<growable::GrowableHeap<slab::SlabAlloc<linked_list::LinkedListAlloc>>>,
+>;
+
+#[cfg(feature = "bump-alloc")]
+pub const fn new_kernel_heap() -> KernelHeap {
+    stats::TrackingHeap::new(harden(growable::GrowableHeap::new(bump::BumpAlloc::new())))
+}
+
+#[cfg(not(feature = "bump-alloc"))]
+pub const fn new_kernel_heap() -> KernelHeap {
+    stats::TrackingHeap::new(harden(growable::GrowableHeap::new(slab::SlabAlloc::new(
+        linked_list::LinkedListAlloc::new(),
+    ))))
+}
+
+/// With the heap-redzone feature every block gets red zones around it and is
+/// poisoned when freed, see `redzone`.
+#[cfg(feature = "heap-redzone")]
+type Hardened<A> = redzone::RedZoneHeap<A>;
+#[cfg(not(feature = "heap-redzone"))]
+type Hardened<A> = A;
+
+#[cfg(feature = "heap-redzone")]
+const fn harden<A: HeapBackend>(heap: A) -> Hardened<A> {
+    redzone::RedZoneHeap::new(heap)
+}
+
+#[cfg(not(feature = "heap-redzone"))]
+const fn harden<A: HeapBackend>(heap: A) -> Hardened<A> {
+    heap
+}
+
+/// Current usage of the kernel heap.
+#[allow(dead_code)]
+pub fn stats() -> stats::AllocStats {
+    crate::ALLOC.lock().stats()
+}
+
+/// Print usage of the kernel heap and every layer below it.
+pub fn dump_stats() {
+    crate::ALLOC.lock().print_stats();
+}
+
+/// Print every kernel heap allocation that has not been freed yet.
+#[cfg(feature = "debug-alloc")]
+pub fn dump_live_allocations() {
+    crate::ALLOC.lock().print_live_allocations();
+}
+
+/// Align a given address upward to the `align` boundary
+///
+/// `align` must be a power of 2.
+fn align_up(addr: usize, align: usize) -> usize {
+    (addr + align - 1) & !(align - 1)
+}
+
+/// Free memory currently held by a heap.
+#[derive(Clone, Copy, Default)]
+pub struct FreeSpace {
+    pub total: usize,
+    pub largest: usize,
+}
+
+/// A heap that manages a region of already mapped memory.
+///
+/// Implementors do not lock, they are wrapped in a `SpinMutex` which provides
+/// the `GlobalAlloc` impl.
+pub trait HeapBackend {
+    /// Hand the heap the memory in `[start, start + size)`.
+    ///
+    /// # Safety
+    /// The region must be mapped, writable and not used by anything else.
+    unsafe fn init(&mut self, start: usize, size: usize);
+
+    /// Add `[start, start + size)`, which directly follows the memory the
+    /// heap already manages.
+    ///
+    /// # Safety
+    /// Same as `init`.
+    unsafe fn extend(&mut self, start: usize, size: usize);
+
+    /// Start of the free memory that reaches up to `top`, the end of the
+    /// heap. Returns `top` if the last byte of the heap is in use.
+    fn free_tail(&self, top: usize) -> usize;
+
+    /// Stop using the memory from `from` up to the end of the heap.
+    ///
+    /// # Safety
+    /// `from` must not be below `free_tail`.
+    unsafe fn release_tail(&mut self, from: usize);
+
+    /// How much memory is free, and the biggest single free block.
+    fn free_space(&self) -> FreeSpace;
+
+    /// Same contract as `GlobalAlloc::alloc`, returns null when out of memory.
+    unsafe fn alloc(&mut self, layout: Layout) -> *mut u8;
+
+    /// Same contract as `GlobalAlloc::dealloc`.
+    unsafe fn dealloc(&mut self, ptr: *mut u8, layout: Layout);
+
+    /// Dump usage information, if the heap keeps any.
+    fn print_stats(&self) {}
+}
+
+/// Map fresh frames at `HEAP_START` and hand them to `heap`.
+pub fn init_heap<A: HeapBackend>(heap: &SpinMutex<A>) {
+    let start = HEAP_START;
+    let end = start + HEAP_FRAMES * FRAME_SIZE;
+
+    // growing the heap takes FRAMES with the heap locked, so never the
+    // other way round
+    let mut frames = crate::FRAMES.lock();
+    for page in (start..end).step_by(FRAME_SIZE) {
+        let frame = frames.allocate().expect("Usable memory required");
+        unsafe {
+            paging::map(page, frame, PageFlags::WRITABLE, &mut frames).expect("map heap");
+        }
+    }
+    drop(frames);
+
+    println!(
+        "heap start segment: {:#4x}, end segment: {:#4x}",
+        start, end
+    );
+
+    unsafe { heap.lock().init(start, end - start) };
+}
+
+unsafe impl<A: HeapBackend> GlobalAlloc for SpinMutex<A> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = unsafe { self.lock().alloc(layout) };
+
+        if ptr.is_null() {
+            println!(
+                "ran out of memory! required {}, align {}",
+                layout.size(),
+                layout.align()
+            );
+        }
+
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { self.lock().dealloc(ptr, layout) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::alloc::{alloc, dealloc};
+    use alloc::boxed::Box;
+    use alloc::vec::Vec;
+
+    #[test_case]
+    fn box_lives_in_heap() {
+        let value = Box::new(41);
+        assert_eq!(*value + 1, 42);
+        let addr = &raw const *value as usize;
+        assert!((HEAP_START..HEAP_END).contains(&addr));
+    }
+
+    #[test_case]
+    fn large_vec_grows_heap() {
+        let v: Vec<u32> = (0..100_000).collect();
+        assert_eq!(v.iter().map(|&x| u64::from(x)).sum::<u64>(), 4_999_950_000);
+    }
+
+    #[test_case]
+    fn allocations_are_freed() {
+        let before = stats();
+        for i in 0..10_000 {
+            let value = Box::new([i as u8; 64]);
+            assert_eq!(value[63], i as u8);
+        }
+        let after = stats();
+        assert_eq!(after.live_allocations, before.live_allocations);
+        assert_eq!(after.bytes_in_use, before.bytes_in_use);
+        assert_eq!(after.total_frees - before.total_frees, 10_000);
+    }
+
+    #[test_case]
+    fn alignment_is_honoured() {
+        for align in [8, 64, 512, 4096] {
+            let layout = Layout::from_size_align(24, align).expect("valid layout");
+            let ptr = unsafe { alloc(layout) };
+            assert!(!ptr.is_null());
+            assert_eq!(ptr as usize % align, 0);
+            unsafe { dealloc(ptr, layout) };
+        }
+    }
+
+    #[test_case]
+    fn frees_are_reused() {
+        let layout = Layout::from_size_align(256, 8).expect("valid layout");
+        let first = unsafe { alloc(layout) };
+        unsafe { dealloc(first, layout) };
+        let second = unsafe { alloc(layout) };
+        unsafe { dealloc(second, layout) };
+        // the bump allocator never reuses anything
+        if !cfg!(feature = "bump-alloc") {
+            assert_eq!(first, second);
+        }
+    }
+}
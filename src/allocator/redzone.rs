@@ -0,0 +1,151 @@
+// Red zones and poisoning to catch heap corruption.
+//
+// Every block is padded with a header, a front red zone and a back red zone:
+//
+//   | header | front red zone | user data | back red zone |
+//                             ^ pointer handed out
+//
+// The header remembers the requested size and where the block was
+// allocated. On free both red zones must still hold the canary byte,
+// otherwise something wrote past the ends of the block. After the checks
+// everything from the front red zone on is filled with the poison byte, so
+// a second free of the same block finds poison instead of canaries, and
+// stale readers see an obviously bogus pattern instead of plausible data.
+// The header is kept, so even the second free can say where the block came
+// from.
+
+use super::{FreeSpace, HeapBackend, align_up};
+use crate::utils::stack::return_addresses;
+use core::alloc::Layout;
+
+const RED_ZONE: usize = 16;
+const CANARY: u8 = 0xcb;
+const POISON: u8 = 0xdf;
+const SITE_DEPTH: usize = 4;
+
+#[repr(C)]
+struct Header {
+    // left to the inner heap, which keeps its free list node at the start
+    // of a freed block
+    _free_node: [usize; 2],
+    size: usize,
+    site: [usize; SITE_DEPTH],
+}
+
+/// Padded layout of a block, and the offset of the user data within it.
+fn padded(layout: Layout) -> (Layout, usize) {
+    let front = align_up(size_of::<Header>() + RED_ZONE, layout.align());
+    let outer = Layout::from_size_align(
+        front + layout.size() + RED_ZONE,
+        layout.align().max(align_of::<Header>()),
+    )
+    .expect("red zone layout");
+
+    (outer, front)
+}
+
+fn is_filled(start: usize, len: usize, byte: u8) -> bool {
+    let zone = unsafe { core::slice::from_raw_parts(start as *const u8, len) };
+    zone.iter().all(|&b| b == byte)
+}
+
+pub struct RedZoneHeap<A: HeapBackend> {
+    inner: A,
+}
+
+impl<A: HeapBackend> RedZoneHeap<A> {
+    pub const fn new(inner: A) -> Self {
+        RedZoneHeap { inner }
+    }
+
+    fn corrupted(what: &str, user: usize, header: &Header) -> ! {
+        panic!(
+            "heap corruption: {} of block {:#x} ({} bytes), allocated from {:x?}",
+            what, user, header.size, header.site
+        );
+    }
+}
+
+impl<A: HeapBackend> HeapBackend for RedZoneHeap<A> {
+    unsafe fn init(&mut self, start: usize, size: usize) {
+        unsafe { self.inner.init(start, size) };
+    }
+
+    unsafe fn extend(&mut self, start: usize, size: usize) {
+        unsafe { self.inner.extend(start, size) };
+    }
+
+    fn free_tail(&self, top: usize) -> usize {
+        self.inner.free_tail(top)
+    }
+
+    unsafe fn release_tail(&mut self, from: usize) {
+        unsafe { self.inner.release_tail(from) };
+    }
+
+    fn free_space(&self) -> FreeSpace {
+        self.inner.free_space()
+    }
+
+    #[inline(always)]
+    unsafe fn alloc(&mut self, layout: Layout) -> *mut u8 {
+        let (outer, front) = padded(layout);
+        let block = unsafe { self.inner.alloc(outer) };
+        if block.is_null() {
+            return block;
+        }
+
+        let mut header = Header {
+            _free_node: [0; 2],
+            size: layout.size(),
+            site: [0; SITE_DEPTH],
+        };
+        return_addresses(&mut header.site);
+
+        unsafe {
+            (block as *mut Header).write(header);
+
+            let header_end = block.add(size_of::<Header>());
+            header_end.write_bytes(CANARY, front - size_of::<Header>());
+            block
+                .add(front + layout.size())
+                .write_bytes(CANARY, RED_ZONE);
+
+            block.add(front)
+        }
+    }
+
+    unsafe fn dealloc(&mut self, ptr: *mut u8, layout: Layout) {
+        let (outer, front) = padded(layout);
+        let user = ptr as usize;
+        let block = user - front;
+        let header = unsafe { &*(block as *const Header) };
+
+        let front_zone = block + size_of::<Header>();
+        let front_len = front - size_of::<Header>();
+        if is_filled(front_zone, front_len, POISON) {
+            Self::corrupted("second free", user, header);
+        }
+
+        if header.size != layout.size() {
+            Self::corrupted("header overwritten or wrong layout on free", user, header);
+        }
+
+        if !is_filled(front_zone, front_len, CANARY) {
+            Self::corrupted("write before the start", user, header);
+        }
+
+        if !is_filled(user + layout.size(), RED_ZONE, CANARY) {
+            Self::corrupted("write past the end", user, header);
+        }
+
+        unsafe {
+            (front_zone as *mut u8).write_bytes(POISON, outer.size() - size_of::<Header>());
+            self.inner.dealloc(block as *mut u8, outer);
+        }
+    }
+
+    fn print_stats(&self) {
+        self.inner.print_stats();
+    }
+}
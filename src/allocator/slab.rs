@@ -0,0 +1,179 @@
+// Size-class allocator for small objects.
+//
+// Each cache hands out objects of one power-of-two size, carved out of whole
+// pages taken from the backing heap. Free objects are kept in an intrusive
+// list, so both allocation and freeing are a single pointer swap. Because
+// pages are page aligned and every class size divides the page size, an
+// object of class N is always aligned to N.
+//
+// Anything bigger than the largest class, or more strictly aligned than its
+// size class, goes straight to the backing heap.
+
+use super::{FreeSpace, HeapBackend};
+use crate::{paging::PAGE_SIZE, println};
+use core::alloc::Layout;
+use core::ptr::null_mut;
+
+const SIZE_CLASSES: [usize; 8] = [16, 32, 64, 128, 256, 512, 1024, 2048];
+
+struct FreeObject {
+    next: *mut FreeObject,
+}
+
+/// Usage of a single size class.
+#[derive(Clone, Copy)]
+pub struct CacheStats {
+    pub object_size: usize,
+    pub pages: usize,
+    pub in_use: usize,
+    pub free: usize,
+}
+
+struct SlabCache {
+    free_list: *mut FreeObject,
+    stats: CacheStats,
+}
+
+impl SlabCache {
+    const fn new(object_size: usize) -> Self {
+        SlabCache {
+            free_list: null_mut(),
+            stats: CacheStats {
+                object_size,
+                pages: 0,
+                in_use: 0,
+                free: 0,
+            },
+        }
+    }
+
+    /// Take a page from `backing` and split it into free objects.
+    unsafe fn grow<A: HeapBackend>(&mut self, backing: &mut A) -> bool {
+        let layout = Layout::from_size_align(PAGE_SIZE, PAGE_SIZE).expect("page layout");
+        let page = unsafe { backing.alloc(layout) } as usize;
+        if page == 0 {
+            return false;
+        }
+
+        let size = self.stats.object_size;
+        for obj in (page..page + PAGE_SIZE).step_by(size).rev() {
+            let obj = obj as *mut FreeObject;
+            unsafe {
+                obj.write(FreeObject {
+                    next: self.free_list,
+                })
+            };
+            self.free_list = obj;
+        }
+
+        self.stats.pages += 1;
+        self.stats.free += PAGE_SIZE / size;
+        true
+    }
+
+    unsafe fn alloc<A: HeapBackend>(&mut self, backing: &mut A) -> *mut u8 {
+        if self.free_list.is_null() && !unsafe { self.grow(backing) } {
+            return null_mut();
+        }
+
+        let obj = self.free_list;
+        self.free_list = unsafe { (*obj).next };
+        self.stats.in_use += 1;
+        self.stats.free -= 1;
+        obj as *mut u8
+    }
+
+    unsafe fn dealloc(&mut self, ptr: *mut u8) {
+        let obj = ptr as *mut FreeObject;
+        unsafe {
+            obj.write(FreeObject {
+                next: self.free_list,
+            })
+        };
+        self.free_list = obj;
+        self.stats.in_use -= 1;
+        self.stats.free += 1;
+    }
+}
+
+pub struct SlabAlloc<A: HeapBackend> {
+    caches: [SlabCache; SIZE_CLASSES.len()],
+    backing: A,
+}
+
+// the free lists only point into pages owned by the backing heap
+unsafe impl<A: HeapBackend + Send> Send for SlabAlloc<A> {}
+
+impl<A: HeapBackend> SlabAlloc<A> {
+    pub const fn new(backing: A) -> Self {
+        SlabAlloc {
+            caches: [
+                SlabCache::new(SIZE_CLASSES[0]),
+                SlabCache::new(SIZE_CLASSES[1]),
+                SlabCache::new(SIZE_CLASSES[2]),
+                SlabCache::new(SIZE_CLASSES[3]),
+                SlabCache::new(SIZE_CLASSES[4]),
+                SlabCache::new(SIZE_CLASSES[5]),
+                SlabCache::new(SIZE_CLASSES[6]),
+                SlabCache::new(SIZE_CLASSES[7]),
+            ],
+            backing,
+        }
+    }
+
+    /// Index of the cache serving `layout`, if it is small enough.
+    fn size_class(layout: Layout) -> Option<usize> {
+        let size = layout.size().max(layout.align());
+        SIZE_CLASSES.iter().position(|&class| size <= class)
+    }
+
+    pub fn cache_stats(&self) -> impl Iterator<Item = CacheStats> + '_ {
+        self.caches.iter().map(|c| c.stats)
+    }
+}
+
+impl<A: HeapBackend> HeapBackend for SlabAlloc<A> {
+    unsafe fn init(&mut self, start: usize, size: usize) {
+        unsafe { self.backing.init(start, size) };
+    }
+
+    unsafe fn extend(&mut self, start: usize, size: usize) {
+        unsafe { self.backing.extend(start, size) };
+    }
+
+    fn free_tail(&self, top: usize) -> usize {
+        self.backing.free_tail(top)
+    }
+
+    unsafe fn release_tail(&mut self, from: usize) {
+        unsafe { self.backing.release_tail(from) };
+    }
+
+    fn free_space(&self) -> FreeSpace {
+        self.backing.free_space()
+    }
+
+    unsafe fn alloc(&mut self, layout: Layout) -> *mut u8 {
+        match Self::size_class(layout) {
+            Some(i) => unsafe { self.caches[i].alloc(&mut self.backing) },
+            None => unsafe { self.backing.alloc(layout) },
+        }
+    }
+
+    unsafe fn dealloc(&mut self, ptr: *mut u8, layout: Layout) {
+        match Self::size_class(layout) {
+            Some(i) => unsafe { self.caches[i].dealloc(ptr) },
+            None => unsafe { self.backing.dealloc(ptr, layout) },
+        }
+    }
+
+    fn print_stats(&self) {
+        println!("----- slab caches -----");
+        for stats in self.cache_stats() {
+            println!(
+                "{:>5} bytes: {} pages, {} in use, {} free",
+                stats.object_size, stats.pages, stats.in_use, stats.free
+            );
+        }
+    }
+}
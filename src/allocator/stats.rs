@@ -0,0 +1,215 @@
+// Usage accounting wrapped around the kernel heap.
+//
+// With the `debug-alloc` feature every live allocation is also recorded
+// together with the return addresses that led to it, so leaks can be listed
+// and resolved with addr2line. The record table has a fixed size because it
+// obviously cannot live on the heap it is tracking.
+
+use super::{FreeSpace, HeapBackend};
+use crate::println;
+use core::alloc::Layout;
+
+/// Snapshot of heap usage.
+#[derive(Clone, Copy, Default)]
+pub struct AllocStats {
+    pub bytes_in_use: usize,
+    pub peak_bytes_in_use: usize,
+    pub live_allocations: usize,
+    pub total_allocations: usize,
+    pub total_frees: usize,
+    pub free: FreeSpace,
+}
+
+impl AllocStats {
+    /// How much of the free memory is unusable for one big allocation, in
+    /// percent. 0 means all free memory is a single block.
+    pub fn fragmentation(&self) -> usize {
+        if self.free.total == 0 {
+            return 0;
+        }
+
+        100 - self.free.largest * 100 / self.free.total
+    }
+}
+
+#[cfg(feature = "debug-alloc")]
+mod live {
+    use crate::utils::stack::return_addresses;
+
+    pub const SITE_DEPTH: usize = 6;
+    const MAX_RECORDS: usize = 512;
+
+    #[derive(Clone, Copy)]
+    pub struct Record {
+        pub ptr: usize,
+        pub size: usize,
+        pub site: [usize; SITE_DEPTH],
+    }
+
+    const EMPTY: Record = Record {
+        ptr: 0,
+        size: 0,
+        site: [0; SITE_DEPTH],
+    };
+
+    pub struct LiveTable {
+        records: [Record; MAX_RECORDS],
+        pub untracked: usize,
+    }
+
+    impl LiveTable {
+        pub const fn new() -> Self {
+            LiveTable {
+                records: [EMPTY; MAX_RECORDS],
+                untracked: 0,
+            }
+        }
+
+        #[inline(always)]
+        pub fn insert(&mut self, ptr: usize, size: usize) {
+            let Some(slot) = self.records.iter_mut().find(|r| r.ptr == 0) else {
+                self.untracked += 1;
+                return;
+            };
+
+            let mut site = [0; SITE_DEPTH];
+            return_addresses(&mut site);
+            *slot = Record { ptr, size, site };
+        }
+
+        pub fn remove(&mut self, ptr: usize) {
+            match self.records.iter_mut().find(|r| r.ptr == ptr) {
+                Some(record) => *record = EMPTY,
+                None => self.untracked = self.untracked.saturating_sub(1),
+            }
+        }
+
+        pub fn iter(&self) -> impl Iterator<Item = &Record> {
+            self.records.iter().filter(|r| r.ptr != 0)
+        }
+    }
+}
+
+pub struct TrackingHeap<A: HeapBackend> {
+    inner: A,
+    stats: AllocStats,
+    #[cfg(feature = "debug-alloc")]
+    live: live::LiveTable,
+}
+
+impl<A: HeapBackend> TrackingHeap<A> {
+    pub const fn new(inner: A) -> Self {
+        TrackingHeap {
+            inner,
+            stats: AllocStats {
+                bytes_in_use: 0,
+                peak_bytes_in_use: 0,
+                live_allocations: 0,
+                total_allocations: 0,
+                total_frees: 0,
+                free: FreeSpace {
+                    total: 0,
+                    largest: 0,
+                },
+            },
+            #[cfg(feature = "debug-alloc")]
+            live: live::LiveTable::new(),
+        }
+    }
+
+    pub fn stats(&self) -> AllocStats {
+        AllocStats {
+            free: self.inner.free_space(),
+            ..self.stats
+        }
+    }
+
+    /// List every allocation that has not been freed yet.
+    #[cfg(feature = "debug-alloc")]
+    pub fn print_live_allocations(&self) {
+        println!("----- live allocations -----");
+        for record in self.live.iter() {
+            crate::print!("{:#x} ({} bytes) from", record.ptr, record.size);
+            for addr in record.site.iter().take_while(|&&a| a != 0) {
+                crate::print!(" {:#x}", addr);
+            }
+            println!();
+        }
+
+        if self.live.untracked > 0 {
+            println!(
+                "{} allocations not tracked, table full",
+                self.live.untracked
+            );
+        }
+    }
+}
+
+impl<A: HeapBackend> HeapBackend for TrackingHeap<A> {
+    unsafe fn init(&mut self, start: usize, size: usize) {
+        unsafe { self.inner.init(start, size) };
+    }
+
+    unsafe fn extend(&mut self, start: usize, size: usize) {
+        unsafe { self.inner.extend(start, size) };
+    }
+
+    fn free_tail(&self, top: usize) -> usize {
+        self.inner.free_tail(top)
+    }
+
+    unsafe fn release_tail(&mut self, from: usize) {
+        unsafe { self.inner.release_tail(from) };
+    }
+
+    fn free_space(&self) -> FreeSpace {
+        self.inner.free_space()
+    }
+
+    #[inline(always)]
+    unsafe fn alloc(&mut self, layout: Layout) -> *mut u8 {
+        let ptr = unsafe { self.inner.alloc(layout) };
+        if ptr.is_null() {
+            return ptr;
+        }
+
+        self.stats.bytes_in_use += layout.size();
+        self.stats.peak_bytes_in_use = self.stats.peak_bytes_in_use.max(self.stats.bytes_in_use);
+        self.stats.live_allocations += 1;
+        self.stats.total_allocations += 1;
+
+        #[cfg(feature = "debug-alloc")]
+        self.live.insert(ptr as usize, layout.size());
+
+        ptr
+    }
+
+    unsafe fn dealloc(&mut self, ptr: *mut u8, layout: Layout) {
+        unsafe { self.inner.dealloc(ptr, layout) };
+
+        self.stats.bytes_in_use -= layout.size();
+        self.stats.live_allocations -= 1;
+        self.stats.total_frees += 1;
+
+        #[cfg(feature = "debug-alloc")]
+        self.live.remove(ptr as usize);
+    }
+
+    fn print_stats(&self) {
+        let stats = self.stats();
+        println!("----- heap usage -----");
+        println!(
+            "in use: {} bytes (peak {}), live allocations: {}",
+            stats.bytes_in_use, stats.peak_bytes_in_use, stats.live_allocations
+        );
+        println!(
+            "allocations: {}, frees: {}, free: {} bytes, largest free block: {}, fragmentation: {}%",
+            stats.total_allocations,
+            stats.total_frees,
+            stats.free.total,
+            stats.free.largest,
+            stats.fragmentation()
+        );
+        self.inner.print_stats();
+    }
+}
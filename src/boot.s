@@ -5,29 +5,95 @@
 .set MB_MAGIC, 0x1BADB002
 .set MB_CHECKSUM, -(MB_MAGIC + MB_FLAGS)
 
+// Must match KERNEL_OFFSET in linker.ld and paging.rs
+.set KERNEL_OFFSET, 0xC0000000
+.set PAGE_PRESENT_WRITABLE, 0x003
+.set CR0_PG_WP, 0x80010000
+
 // Multiboot header
-.section .multiboot
+.section .multiboot.data, "a"
 .align 4
 .long MB_MAGIC
 .long MB_FLAGS
 .long MB_CHECKSUM
 
-// Allocate 16kb stack with 16-byte alignment
+// Allocate 16kb stack, page aligned so that the page below it can be left
+// unmapped as a guard against overflows, see paging.rs
 .section .bss
-.align 16
+.align 4096
+.global stack_guard
+stack_guard:
+    .skip 4096
 stack_bottom:
     .skip 16384 // 16kb stack
 stack_top:
 
+// Page directory built before entering the kernel, paging::init keeps using
+// it as the kernel page directory
+.align 4096
+boot_page_directory:
+    .skip 4096
+boot_page_table:
+    .skip 4096
+
 // Entry point to the kernel
-.section .text
+// This runs at its physical address with paging disabled, so every higher
+// half symbol it touches has to be translated by hand.
+.section .multiboot.text, "ax"
 .global _start
 .type _start, @function
 _start:
     // multiboot specifies that we boot into 32-bit protected mode
+    // eax and ebx hold the multiboot magic and info, leave them alone
+
+    // map the first 4 MiB with a single page table
+    mov $(boot_page_table - KERNEL_OFFSET), %edi
+    mov $PAGE_PRESENT_WRITABLE, %esi
+    mov $1024, %ecx
+1:
+    mov %esi, (%edi)
+    add $4096, %esi
+    add $4, %edi
+    loop 1b
+
+    // use the table both as an identity mapping, so that we keep running
+    // after paging is on, and at KERNEL_OFFSET where the kernel is linked
+    mov $(boot_page_table - KERNEL_OFFSET + PAGE_PRESENT_WRITABLE), %edx
+    mov %edx, (boot_page_directory - KERNEL_OFFSET)
+    mov %edx, (boot_page_directory - KERNEL_OFFSET + 768 * 4)
+
+    // last entry points back at the directory, see paging.rs
+    mov $(boot_page_directory - KERNEL_OFFSET + PAGE_PRESENT_WRITABLE), %edx
+    mov %edx, (boot_page_directory - KERNEL_OFFSET + 1023 * 4)
+
+    mov $(boot_page_directory - KERNEL_OFFSET), %edx
+    mov %edx, %cr3
+
+    mov %cr0, %edx
+    or $CR0_PG_WP, %edx
+    mov %edx, %cr0
+
+    // absolute jump, a relative one would stay in the lower half
+    mov $higher_half, %edx
+    jmp *%edx
 
-    // setup the stack
+// set size of _start symbol to current location - starting location
+.size _start, . - _start
+
+.section .text
+higher_half:
+    // the identity mapping is no longer needed
+    movl $0, boot_page_directory
+    mov %cr3, %edx
+    mov %edx, %cr3
+
+    // setup the stack, a zero frame pointer marks the outermost frame for
+    // stack walks
     mov $stack_top, %esp
+    xor %ebp, %ebp
+
+    // the multiboot info pointer is physical
+    add $KERNEL_OFFSET, %ebx
 
     // push multiboot info as args to kernel_main
     // reversed calling order: last argument first
@@ -42,6 +108,3 @@ _start:
 1:
     hlt
     jmp 1b
-
-// set size of _start symbol to current location - starting location
-.size _start, . - _start
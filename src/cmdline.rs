@@ -0,0 +1,42 @@
+// The kernel command line, as handed over by the bootloader.
+//
+// Multiboot leaves it somewhere in low memory the frame allocator doesn't
+// know about, so `init` copies it before anything gets allocated. Options
+// are separated by spaces and are either `key=value` or just `key`.
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+const MAX_LEN: usize = 256;
+
+// written once by init before LEN is set, read only after
+static mut BUFFER: [u8; MAX_LEN] = [0; MAX_LEN];
+static LEN: AtomicUsize = AtomicUsize::new(0);
+
+/// Keep a copy of `cmdline`, cut off after 256 bytes.
+pub fn init(cmdline: &str) {
+    let mut len = cmdline.len().min(MAX_LEN);
+    while !cmdline.is_char_boundary(len) {
+        len -= 1;
+    }
+
+    let buffer = unsafe { (&raw mut BUFFER).as_mut() }.expect("buffer is static");
+    buffer[..len].copy_from_slice(&cmdline.as_bytes()[..len]);
+    LEN.store(len, Ordering::Release);
+}
+
+pub fn get() -> &'static str {
+    let len = LEN.load(Ordering::Acquire);
+    let buffer = unsafe { (&raw const BUFFER).as_ref() }.expect("buffer is static");
+    // copied from a str, cut at a char boundary
+    unsafe { core::str::from_utf8_unchecked(&buffer[..len]) }
+}
+
+/// Options as `(key, value)`, the value being `None` for a bare key.
+pub fn options() -> impl Iterator<Item = (&'static str, Option<&'static str>)> {
+    get()
+        .split_ascii_whitespace()
+        .map(|option| match option.split_once('=') {
+            Some((key, value)) => (key, Some(value)),
+            None => (option, None),
+        })
+}
@@ -0,0 +1,123 @@
+// The kernel message buffer.
+//
+// Everything printed or logged is also kept here, so output that scrolled
+// off the screen, or was printed before the serial port was up, can still
+// be read. Once the buffer is full the oldest bytes are overwritten.
+//
+// Every byte has a position, counting from boot, which readers use to pick
+// up where they left off. A reader that falls behind by more than the
+// buffer size just misses what was overwritten.
+
+use crate::interrupt::without_interrupts;
+use crate::utils::mutex::SpinMutex;
+
+use core::fmt;
+
+const SIZE: usize = 16 * 1024;
+
+struct Ring {
+    buffer: [u8; SIZE],
+    // bytes written since boot, the position of the next one
+    written: u64,
+}
+
+impl Ring {
+    fn oldest(&self) -> u64 {
+        self.written.saturating_sub(SIZE as u64)
+    }
+
+    fn byte_at(&self, pos: u64) -> u8 {
+        self.buffer[(pos % SIZE as u64) as usize]
+    }
+}
+
+impl fmt::Write for Ring {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for &byte in s.as_bytes() {
+            self.buffer[(self.written % SIZE as u64) as usize] = byte;
+            self.written += 1;
+        }
+        Ok(())
+    }
+}
+
+static RING: SpinMutex<Ring> = SpinMutex::new(Ring {
+    buffer: [0; SIZE],
+    written: 0,
+});
+
+// anything can print, interrupt handlers included
+fn with_ring<R>(f: impl FnOnce(&mut Ring) -> R) -> R {
+    without_interrupts(|| f(&mut RING.lock()))
+}
+
+/// Append to the buffer.
+pub fn write_fmt(args: fmt::Arguments) {
+    with_ring(|ring| fmt::Write::write_fmt(ring, args)).expect("ring never fails");
+}
+
+/// Release the lock for a fatal error handler, see
+/// `SpinMutex::force_unlock`.
+pub unsafe fn force_unlock() {
+    unsafe { RING.force_unlock() };
+}
+
+/// Write the whole buffer, oldest first, to `out`. For catching up a
+/// newly attached output on what it missed.
+pub fn replay(out: &mut impl fmt::Write) -> fmt::Result {
+    Reader::new().read_to(out)
+}
+
+/// Reads the buffer from some position on, keeping track of how far it
+/// got.
+pub struct Reader {
+    pos: u64,
+}
+
+#[allow(dead_code)]
+impl Reader {
+    /// Start at the oldest byte still in the buffer.
+    pub fn new() -> Self {
+        Reader {
+            pos: with_ring(|ring| ring.oldest()),
+        }
+    }
+
+    /// Start with whatever is written from now on.
+    pub fn new_at_end() -> Self {
+        Reader {
+            pos: with_ring(|ring| ring.written),
+        }
+    }
+
+    /// Copy as much as fits in `buf`, returns how much that was. Skips over
+    /// anything overwritten since the last read.
+    pub fn read(&mut self, buf: &mut [u8]) -> usize {
+        with_ring(|ring| {
+            self.pos = self.pos.max(ring.oldest());
+            let n = buf.len().min((ring.written - self.pos) as usize);
+            for (i, byte) in buf[..n].iter_mut().enumerate() {
+                *byte = ring.byte_at(self.pos + i as u64);
+            }
+            self.pos += n as u64;
+            n
+        })
+    }
+
+    /// Write everything not read yet to `out`.
+    pub fn read_to(&mut self, out: &mut impl fmt::Write) -> fmt::Result {
+        // out can be slow, e.g. the serial port, copy first to not keep
+        // interrupts off the whole time
+        let mut chunk = [0; 256];
+        loop {
+            let n = self.read(&mut chunk);
+            if n == 0 {
+                return Ok(());
+            }
+            // a chunk or the oldest line can be cut inside a character
+            for part in chunk[..n].utf8_chunks() {
+                out.write_str(part.valid())?;
+            }
+        }
+    }
+}
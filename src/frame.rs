@@ -0,0 +1,186 @@
+use crate::paging::virt_to_phys;
+use crate::{info, multiboot::BootInfo, utils::bits::CanManipulateBits};
+
+unsafe extern "C" {
+    static KERNEL_START: u32;
+    static KERNEL_END: u32;
+}
+
+pub const FRAME_SIZE: usize = 4096;
+
+// enough frames to cover the whole 32-bit physical address space
+const MAX_FRAMES: usize = 1 << 20;
+const BITMAP_WORDS: usize = MAX_FRAMES / 32;
+
+/// Physical frame allocator, one bit per 4 KiB frame.
+///
+/// A set bit means the frame is free. This way the bitmap starts out zeroed
+/// (everything unusable) and lives in .bss rather than bloating .data.
+pub struct BitmapFrameAllocator {
+    bitmap: [u32; BITMAP_WORDS],
+    total: usize,
+    free: usize,
+    next_word: usize, // where to start searching for a free frame
+}
+
+// allow dead code: the single frame API is only used once paging is in place
+#[allow(dead_code)]
+impl BitmapFrameAllocator {
+    pub const fn new() -> Self {
+        BitmapFrameAllocator {
+            bitmap: [0; BITMAP_WORDS],
+            total: 0,
+            free: 0,
+            next_word: 0,
+        }
+    }
+
+    /// Mark every usable region of the multiboot memory map as free, minus
+    /// the kernel image and the multiboot structures themselves.
+    pub unsafe fn init(&mut self, info: *const BootInfo) {
+        let mmap_entries;
+        let mmap_range;
+        unsafe {
+            mmap_entries = (*info).get_mmap_entries();
+            mmap_range = (*info).mmap_range();
+        }
+
+        for entry in mmap_entries.iter().filter(|e| e.type_ == 1) {
+            // anything above 4 GiB is unreachable without PAE
+            let start = entry.base_addr.min(u64::from(u32::MAX)) as usize;
+            let end = (entry.base_addr + entry.length).min(u64::from(u32::MAX)) as usize;
+
+            let first = start.div_ceil(FRAME_SIZE);
+            let last = end / FRAME_SIZE;
+            for frame in first..last {
+                if !self.is_free(frame) {
+                    self.set_free(frame, true);
+                    self.total += 1;
+                    self.free += 1;
+                }
+            }
+        }
+
+        let (kstart, kend);
+        unsafe {
+            kstart = virt_to_phys(&KERNEL_START as *const u32 as usize);
+            kend = virt_to_phys(&KERNEL_END as *const u32 as usize);
+        }
+
+        let info_start = virt_to_phys(info as usize);
+
+        // frame 0 stays reserved so a physical address of 0 is never valid
+        self.reserve_range(0, FRAME_SIZE);
+        self.reserve_range(kstart, kend);
+        self.reserve_range(info_start, info_start + size_of::<BootInfo>());
+        self.reserve_range(mmap_range.start, mmap_range.end);
+
+        info!("{} of {} frames free", self.free, self.total);
+    }
+
+    /// Take every frame overlapping `[start, end)` out of the free pool.
+    pub fn reserve_range(&mut self, start: usize, end: usize) {
+        let first = start / FRAME_SIZE;
+        let last = end.div_ceil(FRAME_SIZE).min(MAX_FRAMES);
+        for frame in first..last {
+            if self.is_free(frame) {
+                self.set_free(frame, false);
+                self.free -= 1;
+            }
+        }
+    }
+
+    /// Hand out a single frame, returning its physical address.
+    pub fn allocate(&mut self) -> Option<usize> {
+        for i in 0..BITMAP_WORDS {
+            let word = (self.next_word + i) % BITMAP_WORDS;
+            if self.bitmap[word] == 0 {
+                continue;
+            }
+
+            let frame = word * 32 + self.bitmap[word].trailing_zeros() as usize;
+            self.set_free(frame, false);
+            self.free -= 1;
+            self.next_word = word;
+            return Some(frame * FRAME_SIZE);
+        }
+
+        None
+    }
+
+    /// Hand out `count` physically contiguous frames, returning the physical
+    /// address of the first one.
+    pub fn allocate_contiguous(&mut self, count: usize) -> Option<usize> {
+        self.allocate_contiguous_below(count, usize::MAX)
+    }
+
+    /// Like `allocate_contiguous`, but the whole run has to end at or below
+    /// the physical address `limit`.
+    pub fn allocate_contiguous_below(&mut self, count: usize, limit: usize) -> Option<usize> {
+        if count == 0 || count > self.free {
+            return None;
+        }
+
+        let mut run_start = 0;
+        let mut run_len = 0;
+        for frame in 0..(limit / FRAME_SIZE).min(MAX_FRAMES) {
+            if !self.is_free(frame) {
+                run_len = 0;
+                continue;
+            }
+
+            if run_len == 0 {
+                run_start = frame;
+            }
+            run_len += 1;
+
+            if run_len == count {
+                for f in run_start..run_start + count {
+                    self.set_free(f, false);
+                }
+                self.free -= count;
+                return Some(run_start * FRAME_SIZE);
+            }
+        }
+
+        None
+    }
+
+    /// Give a frame obtained from `allocate` back to the pool.
+    pub fn deallocate(&mut self, addr: usize) {
+        assert!(addr % FRAME_SIZE == 0, "frame {:#x} is not aligned", addr);
+
+        let frame = addr / FRAME_SIZE;
+        assert!(!self.is_free(frame), "double free of frame {:#x}", addr);
+
+        self.set_free(frame, true);
+        self.free += 1;
+        self.next_word = self.next_word.min(frame / 32);
+    }
+
+    /// Give `count` contiguous frames obtained from `allocate_contiguous` back.
+    pub fn deallocate_contiguous(&mut self, addr: usize, count: usize) {
+        for i in 0..count {
+            self.deallocate(addr + i * FRAME_SIZE);
+        }
+    }
+
+    pub fn free_frames(&self) -> usize {
+        self.free
+    }
+
+    pub fn total_frames(&self) -> usize {
+        self.total
+    }
+
+    #[inline]
+    fn is_free(&self, frame: usize) -> bool {
+        self.bitmap[frame / 32].get_bits((frame % 32) as u32, 1) == 1
+    }
+
+    #[inline]
+    fn set_free(&mut self, frame: usize, free: bool) {
+        let word = &mut self.bitmap[frame / 32];
+        *word = word.set_one_bit((frame % 32) as u32, free);
+    }
+}
@@ -1,5 +1,73 @@
 use core::arch::asm;
 
+pub const KERNEL_CODE_SELECTOR: u16 = 0x08;
+pub const KERNEL_DATA_SELECTOR: u16 = 0x10;
+pub const KERNEL_TSS_SELECTOR: u16 = 0x18;
+pub const DOUBLE_FAULT_TSS_SELECTOR: u16 = 0x20;
+
+const DOUBLE_FAULT_STACK_SIZE: usize = 8192;
+
+/// 32-bit task state segment.
+///
+/// Only used for hardware task switches to the double fault handler: the
+/// CPU saves the interrupted state into `KERNEL_TSS` and loads the state in
+/// `DOUBLE_FAULT_TSS`, which has a stack of its own. Segment fields are 16
+/// bits wide with the upper half reserved.
+#[derive(Clone, Copy)]
+#[repr(C)]
+pub struct TaskStateSegment {
+    pub link: u32,
+    pub esp0: u32,
+    pub ss0: u32,
+    pub esp1: u32,
+    pub ss1: u32,
+    pub esp2: u32,
+    pub ss2: u32,
+    pub cr3: u32,
+    pub eip: u32,
+    pub eflags: u32,
+    pub eax: u32,
+    pub ecx: u32,
+    pub edx: u32,
+    pub ebx: u32,
+    pub esp: u32,
+    pub ebp: u32,
+    pub esi: u32,
+    pub edi: u32,
+    pub es: u32,
+    pub cs: u32,
+    pub ss: u32,
+    pub ds: u32,
+    pub fs: u32,
+    pub gs: u32,
+    pub ldtr: u32,
+    pub iomap: u32, // I/O map base in the upper half
+}
+
+impl TaskStateSegment {
+    const fn new() -> Self {
+        let mut tss = unsafe { core::mem::zeroed::<Self>() };
+        // the I/O permission bitmap starts past the end, i.e. there is none
+        tss.iomap = (size_of::<Self>() as u32) << 16;
+        tss
+    }
+}
+
+#[repr(C, align(16))]
+struct Stack([u8; DOUBLE_FAULT_STACK_SIZE]);
+
+static mut KERNEL_TSS: TaskStateSegment = TaskStateSegment::new();
+static mut DOUBLE_FAULT_TSS: TaskStateSegment = TaskStateSegment::new();
+static mut DOUBLE_FAULT_STACK: Stack = Stack([0; DOUBLE_FAULT_STACK_SIZE]);
+
+/// State of the kernel as it was when the CPU last switched away from it.
+///
+/// # Safety
+/// Only meaningful from within the double fault task.
+pub unsafe fn interrupted_task() -> TaskStateSegment {
+    unsafe { (&raw const KERNEL_TSS).read() }
+}
+
 #[repr(C, packed)]
 struct GdtTable {
     limit: u16,
@@ -121,7 +189,39 @@ impl GdtSegment {
     }
 }
 
-fn create_gdt_entries() -> [u64; 3] {
+fn tss_descriptor(tss: *const TaskStateSegment) -> u64 {
+    // type 0b1001: available 32-bit TSS, a system descriptor
+    GdtSegment::new(tss as u32, size_of::<TaskStateSegment>() as u32 - 1)
+        .with_access_byte(AccessByte::Present)
+        .with_access_byte(AccessByte::Executable)
+        .with_access_byte(AccessByte::Accessed)
+        .as_u64()
+}
+
+/// Prepare the task the CPU switches to on a double fault.
+fn init_double_fault_tss() {
+    let cr3: u32;
+    unsafe {
+        asm!("mov %cr3, {}", out(reg) cr3, options(att_syntax, nomem, nostack));
+    }
+
+    let stack_top = unsafe { (&raw const DOUBLE_FAULT_STACK).add(1) } as u32;
+    let data = u32::from(KERNEL_DATA_SELECTOR);
+
+    let tss = unsafe { (&raw mut DOUBLE_FAULT_TSS).as_mut() }.expect("double fault tss is free");
+    tss.cr3 = cr3;
+    tss.eip = crate::interrupt::double_fault_entry() as u32;
+    tss.eflags = 0x2; // reserved bit, interrupts stay off
+    tss.esp = stack_top;
+    tss.cs = u32::from(KERNEL_CODE_SELECTOR);
+    tss.ss = data;
+    tss.ds = data;
+    tss.es = data;
+    tss.fs = data;
+    tss.gs = data;
+}
+
+fn create_gdt_entries() -> [u64; 5] {
     let code = GdtSegment::new(0, 0xffff_ffff)
         .with_access_byte(AccessByte::Present)
         .with_access_byte(AccessByte::NotSystemDescriptor)
@@ -142,16 +242,21 @@ fn create_gdt_entries() -> [u64; 3] {
 
     let blank = GdtSegment::new(0, 0).as_u64();
 
-    [blank, code, data]
+    let kernel_tss = tss_descriptor(&raw const KERNEL_TSS);
+    let double_fault_tss = tss_descriptor(&raw const DOUBLE_FAULT_TSS);
+
+    [blank, code, data, kernel_tss, double_fault_tss]
 }
 
 pub fn init_gdt() {
+    init_double_fault_tss();
+
     let entries = create_gdt_entries().to_vec().leak();
     let ptr = entries.as_ptr();
-    let size = entries.len() * core::mem::size_of::<GdtSegment>();
+    let size = core::mem::size_of_val(entries);
 
     let gdt: GdtTable = GdtTable {
-        limit: size as u16,
+        limit: (size - 1) as u16,
         base: ptr as u32,
     };
 
@@ -176,4 +281,92 @@ pub fn init_gdt() {
             mov {r}, %gs
         "#, r = out (reg) _, options(att_syntax));
     }
+
+    // the current task needs a TSS too, it is where the CPU saves the
+    // interrupted state when switching to the double fault task
+    unsafe {
+        asm!("ltr {0:x}", in(reg) KERNEL_TSS_SELECTOR, options(att_syntax, nostack, preserves_flags));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::bits::CanManipulateBits;
+
+    fn sgdt() -> GdtTable {
+        let mut gdt = core::mem::MaybeUninit::<GdtTable>::uninit();
+        unsafe {
+            asm!("sgdt ({})", in(reg) gdt.as_mut_ptr(), options(att_syntax, nostack, preserves_flags));
+            gdt.assume_init()
+        }
+    }
+
+    fn entry(index: usize) -> u64 {
+        let gdt = sgdt();
+        assert!(index * 8 < usize::from(gdt.limit));
+        unsafe { (gdt.base as *const u64).add(index).read() }
+    }
+
+    fn descriptor_base(descriptor: u64) -> u32 {
+        (descriptor.get_bits(16, 24) | descriptor.get_bits(56, 8) << 24) as u32
+    }
+
+    // system descriptor type, 0x9 for an available TSS and 0xb for a busy one
+    fn descriptor_type(descriptor: u64) -> u64 {
+        descriptor.get_bits(40, 4)
+    }
+
+    #[test_case]
+    fn gdt_holds_every_descriptor() {
+        assert_eq!(usize::from(sgdt().limit), 5 * 8 - 1);
+        assert_eq!(entry(0), 0);
+    }
+
+    #[test_case]
+    fn segment_registers_are_reloaded() {
+        let (cs, ds, ss): (u16, u16, u16);
+        unsafe {
+            asm!(
+                "mov %cs, {0:x}",
+                "mov %ds, {1:x}",
+                "mov %ss, {2:x}",
+                out(reg) cs,
+                out(reg) ds,
+                out(reg) ss,
+                options(att_syntax, nomem, nostack, preserves_flags)
+            );
+        }
+        assert_eq!(cs, KERNEL_CODE_SELECTOR);
+        assert_eq!(ds, KERNEL_DATA_SELECTOR);
+        assert_eq!(ss, KERNEL_DATA_SELECTOR);
+    }
+
+    #[test_case]
+    fn task_register_holds_kernel_tss() {
+        let tr: u16;
+        unsafe {
+            asm!("str {0:x}", out(reg) tr, options(att_syntax, nomem, nostack, preserves_flags));
+        }
+        assert_eq!(tr, KERNEL_TSS_SELECTOR);
+
+        let descriptor = entry(usize::from(KERNEL_TSS_SELECTOR >> 3));
+        assert_eq!(descriptor_base(descriptor), (&raw const KERNEL_TSS) as u32);
+        // ltr marks it busy
+        assert_eq!(descriptor_type(descriptor), 0xb);
+    }
+
+    #[test_case]
+    fn double_fault_tss_is_ready() {
+        let descriptor = entry(usize::from(DOUBLE_FAULT_TSS_SELECTOR >> 3));
+        assert_eq!(
+            descriptor_base(descriptor),
+            (&raw const DOUBLE_FAULT_TSS) as u32
+        );
+        assert_eq!(descriptor_type(descriptor), 0x9);
+
+        let tss = unsafe { (&raw const DOUBLE_FAULT_TSS).read() };
+        let stack = (&raw const DOUBLE_FAULT_STACK) as u32;
+        assert_eq!({ tss.esp }, stack + DOUBLE_FAULT_STACK_SIZE as u32);
+    }
 }
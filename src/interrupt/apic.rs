@@ -0,0 +1,342 @@
+// Local APIC and I/O APIC drivers.
+//
+// See: Intel SDM Vol. 3A, 11 "Advanced Programmable Interrupt Controller"
+// and the 82093AA I/O APIC datasheet.
+//
+// The I/O APIC takes over from the 8259s: each of its pins (global system
+// interrupts, GSIs) has a redirection entry naming the vector and the CPU it
+// is delivered to. ISA IRQs are usually wired to the pin of the same number,
+// the MADT lists the exceptions. They keep the vectors the 8259s used, so
+// drivers and the dispatcher don't care which controller is in charge. Once
+// an interrupt is delivered, it is acknowledged at the local APIC instead.
+
+use super::{pic, without_interrupts};
+use crate::acpi::{self, IoApicInfo, Madt};
+use crate::info;
+use crate::paging::{self, PAGE_SIZE, PageFlags};
+use crate::time;
+use crate::utils::mutex::SpinMutex;
+
+use alloc::vec::Vec;
+use core::arch::asm;
+use core::arch::x86::__cpuid;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// Where the local APIC sends interrupts it can't attribute to anything.
+pub const SPURIOUS_VECTOR: u8 = 0xff;
+
+const ISA_IRQS: usize = 16;
+
+// CPUID.1:EDX
+const CPUID_APIC: u32 = 1 << 9;
+
+const IA32_APIC_BASE: u32 = 0x1b;
+const APIC_GLOBAL_ENABLE: u32 = 1 << 11;
+
+// local APIC registers, offsets from the base
+const LAPIC_ID: usize = 0x20;
+const LAPIC_VERSION: usize = 0x30;
+const LAPIC_TPR: usize = 0x80;
+const LAPIC_EOI: usize = 0xb0;
+const LAPIC_SVR: usize = 0xf0;
+const LAPIC_LINT0: usize = 0x350;
+const LAPIC_LINT1: usize = 0x360;
+
+const SVR_ENABLE: u32 = 1 << 8;
+
+// bits shared by local vector table and redirection entries
+const DELIVERY_NMI: u32 = 0b100 << 8;
+const ACTIVE_LOW: u32 = 1 << 13;
+const LEVEL_TRIGGERED: u32 = 1 << 15;
+const MASKED: u32 = 1 << 16;
+
+// I/O APIC registers, selected through IOREGSEL and accessed through IOWIN
+const IOREGSEL: usize = 0x00;
+const IOWIN: usize = 0x10;
+const IOAPIC_VERSION: u32 = 0x01;
+const IOAPIC_REDIRECTION: u32 = 0x10;
+
+// virtual address of the local APIC registers, 0 while the 8259s are used
+static LOCAL_APIC: AtomicUsize = AtomicUsize::new(0);
+
+static ROUTING: SpinMutex<Option<IsaRouting>> = SpinMutex::new(None);
+
+unsafe fn read_msr(msr: u32) -> u64 {
+    let (low, high): (u32, u32);
+    unsafe {
+        asm!("rdmsr", in("ecx") msr, out("eax") low, out("edx") high, options(nomem, nostack, preserves_flags));
+    }
+    u64::from(high) << 32 | u64::from(low)
+}
+
+unsafe fn write_msr(msr: u32, value: u64) {
+    unsafe {
+        asm!("wrmsr", in("ecx") msr, in("eax") value as u32, in("edx") (value >> 32) as u32, options(nomem, nostack, preserves_flags));
+    }
+}
+
+fn has_apic() -> bool {
+    time::has_cpuid() && unsafe { __cpuid(1) }.edx & CPUID_APIC != 0
+}
+
+// FRAMES only for the mapping itself, the callers allocate and the heap
+// takes it to grow
+fn map_registers(phys: usize) -> Result<usize, &'static str> {
+    let flags = PageFlags::WRITABLE | PageFlags::NO_CACHE | PageFlags::WRITE_THROUGH;
+    paging::map_physical(phys, PAGE_SIZE, flags, &mut crate::FRAMES.lock())
+}
+
+/// Convert MPS INTI flags from the MADT into redirection entry bits. The
+/// "conforms to the bus" defaults are ISA's: active high, edge triggered.
+fn inti_flags(flags: u16) -> u32 {
+    let mut bits = 0;
+    if flags & 0b11 == 0b11 {
+        bits |= ACTIVE_LOW;
+    }
+    if (flags >> 2) & 0b11 == 0b11 {
+        bits |= LEVEL_TRIGGERED;
+    }
+    bits
+}
+
+struct LocalApic {
+    base: usize,
+}
+
+impl LocalApic {
+    fn read(&self, reg: usize) -> u32 {
+        unsafe { ((self.base + reg) as *const u32).read_volatile() }
+    }
+
+    fn write(&self, reg: usize, value: u32) {
+        unsafe { ((self.base + reg) as *mut u32).write_volatile(value) };
+    }
+
+    fn id(&self) -> u8 {
+        (self.read(LAPIC_ID) >> 24) as u8
+    }
+
+    fn enable(&self, madt: &Madt) {
+        unsafe {
+            let base = read_msr(IA32_APIC_BASE);
+            write_msr(IA32_APIC_BASE, base | u64::from(APIC_GLOBAL_ENABLE));
+        }
+
+        self.write(LAPIC_SVR, SVR_ENABLE | u32::from(SPURIOUS_VECTOR));
+        self.write(LAPIC_TPR, 0);
+
+        // LINT0 is where the 8259s would come in as ExtINT
+        self.write(LAPIC_LINT0, MASKED);
+
+        let id = self.id();
+        for nmi in madt
+            .nmis
+            .iter()
+            .filter(|n| n.processor == 0xff || n.processor == id)
+        {
+            let reg = if nmi.lint == 0 {
+                LAPIC_LINT0
+            } else {
+                LAPIC_LINT1
+            };
+            self.write(reg, DELIVERY_NMI | inti_flags(nmi.flags));
+        }
+    }
+}
+
+struct IoApic {
+    base: usize,
+    gsi_base: u32,
+    pins: u32,
+}
+
+impl IoApic {
+    fn new(info: &IoApicInfo) -> Result<Self, &'static str> {
+        let mut ioapic = IoApic {
+            base: map_registers(info.address)?,
+            gsi_base: info.gsi_base,
+            pins: 0,
+        };
+        ioapic.pins = ((ioapic.read(IOAPIC_VERSION) >> 16) & 0xff) + 1;
+
+        info!(
+            "io apic {} at {:#x}, gsis {}..{}",
+            info.id,
+            info.address,
+            ioapic.gsi_base,
+            ioapic.gsi_base + ioapic.pins
+        );
+        Ok(ioapic)
+    }
+
+    fn read(&self, reg: u32) -> u32 {
+        unsafe {
+            ((self.base + IOREGSEL) as *mut u32).write_volatile(reg);
+            ((self.base + IOWIN) as *const u32).read_volatile()
+        }
+    }
+
+    fn write(&self, reg: u32, value: u32) {
+        unsafe {
+            ((self.base + IOREGSEL) as *mut u32).write_volatile(reg);
+            ((self.base + IOWIN) as *mut u32).write_volatile(value);
+        }
+    }
+
+    fn handles(&self, gsi: u32) -> bool {
+        (self.gsi_base..self.gsi_base + self.pins).contains(&gsi)
+    }
+
+    fn set_redirection(&self, gsi: u32, low: u32, destination: u8) {
+        let reg = IOAPIC_REDIRECTION + (gsi - self.gsi_base) * 2;
+        self.write(reg + 1, u32::from(destination) << 24);
+        self.write(reg, low);
+    }
+
+    fn set_masked(&self, gsi: u32, masked: bool) {
+        let reg = IOAPIC_REDIRECTION + (gsi - self.gsi_base) * 2;
+        let low = self.read(reg);
+        self.write(reg, if masked { low | MASKED } else { low & !MASKED });
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Route {
+    gsi: u32,
+    flags: u32,
+}
+
+/// How ISA IRQs reach the I/O APICs.
+struct IsaRouting {
+    io_apics: Vec<IoApic>,
+    // None for IRQs that aren't connected, like the cascade
+    routes: [Option<Route>; ISA_IRQS],
+}
+
+impl IsaRouting {
+    fn new(madt: &Madt) -> Result<Self, &'static str> {
+        let io_apics = madt
+            .io_apics
+            .iter()
+            .map(IoApic::new)
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let overrides = || {
+            madt.overrides
+                .iter()
+                .filter(|o| (o.irq as usize) < ISA_IRQS)
+        };
+
+        // identity unless the pin was given to another IRQ, e.g. on QEMU
+        // IRQ 0 takes GSI 2, and the cascade IRQ 2 leaves nothing behind
+        let mut routes = [None; ISA_IRQS];
+        for (irq, route) in routes.iter_mut().enumerate() {
+            let gsi = irq as u32;
+            if irq as u8 != pic::CASCADE_IRQ && !overrides().any(|o| o.gsi == gsi) {
+                *route = Some(Route { gsi, flags: 0 });
+            }
+        }
+        for o in overrides().filter(|o| o.irq != pic::CASCADE_IRQ) {
+            routes[o.irq as usize] = Some(Route {
+                gsi: o.gsi,
+                flags: inti_flags(o.flags),
+            });
+        }
+
+        Ok(IsaRouting { io_apics, routes })
+    }
+
+    fn io_apic_for(&self, irq: u8) -> Option<(&IoApic, Route)> {
+        let route = self.routes[irq as usize]?;
+        self.io_apics
+            .iter()
+            .find(|a| a.handles(route.gsi))
+            .map(|a| (a, route))
+    }
+
+    /// Point every ISA IRQ at its usual vector on `destination`, masked.
+    fn program(&self, destination: u8) {
+        for irq in 0..ISA_IRQS as u8 {
+            if let Some((ioapic, route)) = self.io_apic_for(irq) {
+                let low = u32::from(pic::irq_vector(irq)) | route.flags | MASKED;
+                ioapic.set_redirection(route.gsi, low, destination);
+            }
+        }
+    }
+
+    fn set_masked(&self, irq: u8, masked: bool) {
+        if let Some((ioapic, route)) = self.io_apic_for(irq) {
+            ioapic.set_masked(route.gsi, masked);
+        }
+    }
+}
+
+/// Whether interrupts are delivered through the APICs.
+pub fn is_enabled() -> bool {
+    LOCAL_APIC.load(Ordering::Relaxed) != 0
+}
+
+/// Switch interrupt delivery from the 8259s to the APICs described by the
+/// MADT, and disable the 8259s. IRQs unmasked on the 8259s stay unmasked.
+pub fn init() -> Result<(), &'static str> {
+    if !has_apic() {
+        return Err("no local APIC");
+    }
+
+    let madt = acpi::madt()?;
+    if madt.io_apics.is_empty() {
+        return Err("no I/O APIC");
+    }
+
+    let local = LocalApic {
+        base: map_registers(madt.local_apic_address)?,
+    };
+    let routing = IsaRouting::new(&madt)?;
+
+    without_interrupts(|| {
+        let unmasked = !pic::disable();
+
+        local.enable(&madt);
+        routing.program(local.id());
+        for irq in 0..ISA_IRQS as u8 {
+            if unmasked & (1 << irq) != 0 && irq != pic::CASCADE_IRQ {
+                routing.set_masked(irq, false);
+            }
+        }
+
+        LOCAL_APIC.store(local.base, Ordering::Relaxed);
+        *ROUTING.lock() = Some(routing);
+    });
+
+    info!(
+        "local apic {} (version {:#x}), {} cpus, {} io apics, 8259s {}",
+        local.id(),
+        local.read(LAPIC_VERSION) & 0xff,
+        madt.local_apic_ids.len(),
+        madt.io_apics.len(),
+        if madt.legacy_pics {
+            "disabled"
+        } else {
+            "absent"
+        },
+    );
+    Ok(())
+}
+
+fn with_routing(f: impl FnOnce(&IsaRouting)) {
+    without_interrupts(|| f(ROUTING.lock().as_ref().expect("APIC is initialised")));
+}
+
+pub fn mask(irq: u8) {
+    with_routing(|r| r.set_masked(irq, true));
+}
+
+pub fn unmask(irq: u8) {
+    with_routing(|r| r.set_masked(irq, false));
+}
+
+/// Acknowledge the interrupt being handled.
+pub(super) fn end_of_interrupt() {
+    let base = LOCAL_APIC.load(Ordering::Relaxed);
+    unsafe { ((base + LAPIC_EOI) as *mut u32).write_volatile(0) };
+}
@@ -0,0 +1,138 @@
+// Per-vector entry stubs and the dispatcher behind them.
+//
+// Every vector without a dedicated handler in the IDT points at a small
+// stub. The stubs are generated in assembly, 16 bytes apart so their
+// addresses can be computed instead of stored. Each one pushes a zero in
+// place of the error code if the CPU didn't push one, pushes its vector
+// number and jumps to common code, which saves the general purpose registers
+// and calls `dispatch` with a pointer to all of it.
+//
+// Handlers are kept in a table of atomics rather than behind a lock, since
+// an interrupt can arrive while a driver is (un)registering.
+
+use super::{InterruptStackFrame, apic, pic};
+use crate::warn;
+
+use core::arch::global_asm;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+const STUB_SIZE: usize = 16;
+const VECTORS: usize = 256;
+
+// vectors 0 to 31 belong to CPU exceptions, which have their own handlers
+const FIRST_FREE_VECTOR: u8 = 32;
+
+global_asm!(
+    r#"
+    .section .text
+    .balign {stub_size}
+    isr_stubs:
+    .set isr_vector, 0
+    .rept {vectors}
+        .balign {stub_size}
+        // the CPU pushes an error code for these, see exceptions.rs
+        .if isr_vector == 8 || (isr_vector >= 10 && isr_vector <= 14) || isr_vector == 17 || isr_vector == 21 || isr_vector == 29 || isr_vector == 30
+        .else
+        push $0
+        .endif
+        push $isr_vector
+        jmp isr_common
+        .set isr_vector, isr_vector + 1
+    .endr
+
+    isr_common:
+        pusha
+        mov %esp, %eax
+        cld
+        push %eax
+        call {dispatch}
+        add $4, %esp
+        popa
+        add $8, %esp // vector and error code
+        iret
+    "#,
+    stub_size = const STUB_SIZE,
+    vectors = const VECTORS,
+    dispatch = sym dispatch,
+    options(att_syntax)
+);
+
+unsafe extern "C" {
+    fn isr_stubs();
+}
+
+/// Address of the entry stub for `vector`.
+pub(super) fn stub(vector: usize) -> usize {
+    isr_stubs as usize + vector * STUB_SIZE
+}
+
+/// Interrupted state as saved by the entry stubs, in stack order.
+#[derive(Debug)]
+#[repr(C)]
+pub struct InterruptContext {
+    pub edi: u32,
+    pub esi: u32,
+    pub ebp: u32,
+    esp: u32, // skipped by popa, changing it does nothing
+    pub ebx: u32,
+    pub edx: u32,
+    pub ecx: u32,
+    pub eax: u32,
+    pub vector: u32,
+    pub error_code: u32,
+    pub frame: InterruptStackFrame,
+}
+
+pub type Handler = fn(&mut InterruptContext);
+
+// function pointers stored as usize, 0 means no handler
+static HANDLERS: [AtomicUsize; VECTORS] = [const { AtomicUsize::new(0) }; VECTORS];
+
+/// Call `handler` whenever `vector` fires.
+///
+/// End of interrupt is signalled to the interrupt controller after the
+/// handler returns, so IRQ handlers don't need to do it themselves.
+pub fn register_handler(vector: u8, handler: Handler) -> Result<(), &'static str> {
+    if vector < FIRST_FREE_VECTOR {
+        return Err("vector is reserved for CPU exceptions");
+    }
+
+    HANDLERS[vector as usize]
+        .compare_exchange(0, handler as usize, Ordering::AcqRel, Ordering::Acquire)
+        .map(|_| ())
+        .map_err(|_| "vector already has a handler")
+}
+
+/// Stop calling the handler registered for `vector`, if any.
+#[allow(dead_code)]
+pub fn unregister_handler(vector: u8) {
+    HANDLERS[vector as usize].store(0, Ordering::Release);
+}
+
+extern "C" fn dispatch(context: &mut InterruptContext) {
+    let vector = context.vector as u8;
+    // ISA IRQs keep their vectors under either controller
+    let irq = pic::vector_irq(vector);
+    let apic = apic::is_enabled();
+
+    // spurious interrupts must not be acknowledged
+    if apic && vector == apic::SPURIOUS_VECTOR || !apic && irq.is_some_and(pic::is_spurious) {
+        return;
+    }
+
+    match HANDLERS[vector as usize].load(Ordering::Acquire) {
+        0 => warn!("unhandled interrupt {:#x}", vector),
+        raw => {
+            let handler = unsafe { core::mem::transmute::<usize, Handler>(raw) };
+            handler(context);
+        }
+    }
+
+    if let Some(irq) = irq {
+        if apic {
+            apic::end_of_interrupt();
+        } else {
+            pic::end_of_interrupt(irq);
+        }
+    }
+}
@@ -0,0 +1,84 @@
+// Double faults run as a separate hardware task.
+//
+// A double fault is most often caused by a kernel stack overflow: the CPU
+// can't push the frame for a page fault onto the guard page, so it raises a
+// double fault instead, which would need the same broken stack. An interrupt
+// gate would then triple-fault and reset the machine. The IDT entry for
+// vector 8 is therefore a task gate, and the CPU switches to the task
+// described by the double fault TSS in gdt.rs, which has its own stack.
+//
+// The interrupted state is saved into the kernel TSS, which is all there is
+// to look at. There is no way back from here.
+
+use super::InterruptTable;
+use crate::gdt::{self, DOUBLE_FAULT_TSS_SELECTOR};
+use crate::paging;
+use crate::println;
+
+use core::arch::{asm, global_asm};
+
+// The task starts with the error code on top of its stack, which is exactly
+// where a call leaves it for a cdecl function's first argument.
+global_asm!(
+    r#"
+    .section .text
+    double_fault_entry:
+        xor %ebp, %ebp
+        call {handler}
+    "#,
+    handler = sym double_fault_task,
+    options(att_syntax)
+);
+
+unsafe extern "C" {
+    fn double_fault_entry();
+}
+
+/// Address the double fault task starts executing at.
+pub fn entry_point() -> usize {
+    double_fault_entry as usize
+}
+
+extern "C" fn double_fault_task(error_code: u32) -> ! {
+    // whatever was interrupted may have held the screen
+    unsafe {
+        crate::io::vga::WRITER.force_unlock();
+        crate::dmesg::force_unlock();
+        crate::io::serial::force_unlock(crate::io::serial::ComPort::Com1);
+    }
+
+    let state = unsafe { gdt::interrupted_task() };
+    println!(
+        "EXCEPTION 8: double fault (#DF), error code {:#x}",
+        error_code
+    );
+    println!(
+        "  at eip {:#010x}, cs {:#06x}, eflags {:#010x}",
+        state.eip, state.cs, state.eflags
+    );
+    println!(
+        "  eax {:#010x} ebx {:#010x} ecx {:#010x} edx {:#010x}",
+        state.eax, state.ebx, state.ecx, state.edx
+    );
+    println!(
+        "  esi {:#010x} edi {:#010x} ebp {:#010x} esp {:#010x}",
+        state.esi, state.edi, state.ebp, state.esp
+    );
+    println!("  cr3 {:#010x}", state.cr3);
+
+    // the fault happened while pushing, so esp can be just above the guard
+    let guard = paging::stack_guard();
+    if (guard.start..guard.end + 64).contains(&(state.esp as usize)) {
+        println!("kernel stack overflow");
+    }
+
+    println!("halting");
+    loop {
+        unsafe { asm!("cli", "hlt", options(nomem, nostack)) };
+    }
+}
+
+/// Route vector 8 through the double fault task.
+pub(super) fn install(table: &mut InterruptTable) {
+    table.set_task_gate(8, DOUBLE_FAULT_TSS_SELECTOR);
+}
@@ -0,0 +1,224 @@
+// Handlers for the 32 vectors reserved for CPU exceptions.
+//
+// See: Intel SDM Vol. 3A, 6.15 "Exception and Interrupt Reference"
+//
+// Traps (debug, breakpoint, overflow) and NMIs are reported and execution
+// continues after the instruction. Everything else is a fault or an abort:
+// returning would just re-run the faulting instruction forever, so those
+// panic with a description of what happened.
+
+use super::{InterruptStackFrame, InterruptTable};
+use crate::println;
+
+use core::fmt;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Trap,
+    Fault,
+}
+
+struct Exception {
+    vector: u8,
+    mnemonic: &'static str,
+    name: &'static str,
+    kind: Kind,
+}
+
+const fn exception(
+    vector: u8,
+    mnemonic: &'static str,
+    name: &'static str,
+    kind: Kind,
+) -> Exception {
+    Exception {
+        vector,
+        mnemonic,
+        name,
+        kind,
+    }
+}
+
+const EXCEPTIONS: [Exception; 32] = [
+    exception(0, "#DE", "divide error", Kind::Fault),
+    exception(1, "#DB", "debug", Kind::Trap),
+    exception(2, "NMI", "non-maskable interrupt", Kind::Trap),
+    exception(3, "#BP", "breakpoint", Kind::Trap),
+    exception(4, "#OF", "overflow", Kind::Trap),
+    exception(5, "#BR", "bound range exceeded", Kind::Fault),
+    exception(6, "#UD", "invalid opcode", Kind::Fault),
+    exception(7, "#NM", "device not available", Kind::Fault),
+    exception(8, "#DF", "double fault", Kind::Fault),
+    exception(9, "", "coprocessor segment overrun", Kind::Fault),
+    exception(10, "#TS", "invalid TSS", Kind::Fault),
+    exception(11, "#NP", "segment not present", Kind::Fault),
+    exception(12, "#SS", "stack-segment fault", Kind::Fault),
+    exception(13, "#GP", "general protection fault", Kind::Fault),
+    exception(14, "#PF", "page fault", Kind::Fault),
+    exception(15, "", "reserved", Kind::Fault),
+    exception(16, "#MF", "x87 floating-point exception", Kind::Fault),
+    exception(17, "#AC", "alignment check", Kind::Fault),
+    exception(18, "#MC", "machine check", Kind::Fault),
+    exception(19, "#XM", "SIMD floating-point exception", Kind::Fault),
+    exception(20, "#VE", "virtualization exception", Kind::Fault),
+    exception(21, "#CP", "control protection exception", Kind::Fault),
+    exception(22, "", "reserved", Kind::Fault),
+    exception(23, "", "reserved", Kind::Fault),
+    exception(24, "", "reserved", Kind::Fault),
+    exception(25, "", "reserved", Kind::Fault),
+    exception(26, "", "reserved", Kind::Fault),
+    exception(27, "", "reserved", Kind::Fault),
+    exception(28, "#HV", "hypervisor injection exception", Kind::Fault),
+    exception(29, "#VC", "VMM communication exception", Kind::Fault),
+    exception(30, "#SX", "security exception", Kind::Fault),
+    exception(31, "", "reserved", Kind::Fault),
+];
+
+/// Error code pushed by #TS, #NP, #SS and #GP, which names the segment
+/// selector or IDT vector involved.
+struct SelectorError(u32);
+
+impl fmt::Display for SelectorError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.0 == 0 {
+            return write!(f, "not segment related");
+        }
+
+        let table = match (self.0 >> 1) & 0b11 {
+            0b00 => "GDT",
+            0b10 => "LDT",
+            _ => "IDT",
+        };
+        write!(f, "{} index {:#x}", table, self.0 >> 3)?;
+
+        if self.0 & 1 != 0 {
+            write!(f, ", external event")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Everything known about one occurrence of an exception.
+struct Report<'a> {
+    exception: &'a Exception,
+    frame: &'a InterruptStackFrame,
+    error_code: Option<u32>,
+}
+
+impl fmt::Display for Report<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let e = self.exception;
+        write!(f, "EXCEPTION {}: {}", e.vector, e.name)?;
+        if !e.mnemonic.is_empty() {
+            write!(f, " ({})", e.mnemonic)?;
+        }
+
+        if let Some(code) = self.error_code {
+            write!(f, ", error code {:#x}", code)?;
+            if matches!(e.vector, 10..=13) {
+                write!(f, " [{}]", SelectorError(code))?;
+            }
+        }
+
+        write!(
+            f,
+            "\n  at eip {:#010x}, cs {:#06x}, eflags {:#010x}",
+            self.frame.eip, self.frame.cs, self.frame.eflags
+        )
+    }
+}
+
+fn handle(vector: u8, frame: &InterruptStackFrame, error_code: Option<u32>) {
+    let exception = &EXCEPTIONS[vector as usize];
+    let report = Report {
+        exception,
+        frame,
+        error_code,
+    };
+
+    match exception.kind {
+        Kind::Trap => println!("{}", report),
+        Kind::Fault => panic!("{}", report),
+    }
+}
+
+macro_rules! exception_handler {
+    ($name:ident, $vector:expr) => {
+        extern "x86-interrupt" fn $name(frame: InterruptStackFrame) {
+            handle($vector, &frame, None);
+        }
+    };
+    ($name:ident, $vector:expr, error_code) => {
+        extern "x86-interrupt" fn $name(frame: InterruptStackFrame, error_code: u32) {
+            handle($vector, &frame, Some(error_code));
+        }
+    };
+}
+
+exception_handler!(divide_error, 0);
+exception_handler!(debug, 1);
+exception_handler!(non_maskable_interrupt, 2);
+exception_handler!(breakpoint, 3);
+exception_handler!(overflow, 4);
+exception_handler!(bound_range_exceeded, 5);
+exception_handler!(invalid_opcode, 6);
+exception_handler!(device_not_available, 7);
+exception_handler!(coprocessor_segment_overrun, 9);
+exception_handler!(invalid_tss, 10, error_code);
+exception_handler!(segment_not_present, 11, error_code);
+exception_handler!(stack_segment_fault, 12, error_code);
+exception_handler!(general_protection_fault, 13, error_code);
+exception_handler!(reserved_15, 15);
+exception_handler!(x87_floating_point, 16);
+exception_handler!(alignment_check, 17, error_code);
+exception_handler!(machine_check, 18);
+exception_handler!(simd_floating_point, 19);
+exception_handler!(virtualization, 20);
+exception_handler!(control_protection, 21, error_code);
+exception_handler!(reserved_22, 22);
+exception_handler!(reserved_23, 23);
+exception_handler!(reserved_24, 24);
+exception_handler!(reserved_25, 25);
+exception_handler!(reserved_26, 26);
+exception_handler!(reserved_27, 27);
+exception_handler!(hypervisor_injection, 28);
+exception_handler!(vmm_communication, 29, error_code);
+exception_handler!(security, 30, error_code);
+exception_handler!(reserved_31, 31);
+
+/// Point vectors 0 to 31 at the handlers above, except for the double
+/// fault, which gets a task gate (see double_fault.rs), and page faults
+/// (see page_fault.rs).
+pub(super) fn install(table: &mut InterruptTable) {
+    table.set_interrupt(0, divide_error);
+    table.set_interrupt(1, debug);
+    table.set_interrupt(2, non_maskable_interrupt);
+    table.set_interrupt(3, breakpoint);
+    table.set_interrupt(4, overflow);
+    table.set_interrupt(5, bound_range_exceeded);
+    table.set_interrupt(6, invalid_opcode);
+    table.set_interrupt(7, device_not_available);
+    table.set_interrupt(9, coprocessor_segment_overrun);
+    table.set_interrupt_with_err(10, invalid_tss);
+    table.set_interrupt_with_err(11, segment_not_present);
+    table.set_interrupt_with_err(12, stack_segment_fault);
+    table.set_interrupt_with_err(13, general_protection_fault);
+    table.set_interrupt(15, reserved_15);
+    table.set_interrupt(16, x87_floating_point);
+    table.set_interrupt_with_err(17, alignment_check);
+    table.set_interrupt(18, machine_check);
+    table.set_interrupt(19, simd_floating_point);
+    table.set_interrupt(20, virtualization);
+    table.set_interrupt_with_err(21, control_protection);
+    table.set_interrupt(22, reserved_22);
+    table.set_interrupt(23, reserved_23);
+    table.set_interrupt(24, reserved_24);
+    table.set_interrupt(25, reserved_25);
+    table.set_interrupt(26, reserved_26);
+    table.set_interrupt(27, reserved_27);
+    table.set_interrupt(28, hypervisor_injection);
+    table.set_interrupt_with_err(29, vmm_communication);
+    table.set_interrupt_with_err(30, security);
+    table.set_interrupt(31, reserved_31);
+}
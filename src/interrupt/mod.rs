@@ -0,0 +1,264 @@
+use crate::io::ports::PortAllocator;
+use crate::println;
+use crate::utils::bits::CanManipulateBits;
+
+use core::arch::asm;
+
+#[cfg_attr(not(feature = "apic"), allow(dead_code))]
+pub mod apic;
+mod dispatch;
+mod double_fault;
+mod exceptions;
+pub mod page_fault;
+pub mod pic;
+
+#[allow(unused_imports)] // nothing unregisters yet
+pub use dispatch::{InterruptContext, register_handler, unregister_handler};
+pub use double_fault::entry_point as double_fault_entry;
+
+const IDT_TABLE_SIZE: usize = 256;
+
+type InterruptServiceRoutine = extern "x86-interrupt" fn(InterruptStackFrame);
+type InterruptServiceRoutineWithErrCode = extern "x86-interrupt" fn(InterruptStackFrame, u32);
+
+/// What the CPU pushes onto the stack before entering a handler.
+///
+/// ESP and SS are only pushed on a privilege change, which can't happen
+/// while everything runs in ring 0, so they are left out.
+#[derive(Clone, Copy, Debug)]
+#[repr(C)]
+pub struct InterruptStackFrame {
+    pub eip: u32,
+    pub cs: u32,
+    pub eflags: u32,
+}
+
+static mut INTERRUPT_TABLE: InterruptTable = InterruptTable::new();
+
+#[allow(dead_code)]
+#[repr(u8)]
+enum GateType {
+    Task = 0b0101,
+    Interrupt16Bit = 0b0110,
+    Trap16Bit = 0b0111,
+    Interrupt32Bit = 0b1110,
+    Trap32Bit = 0b1111,
+}
+
+#[allow(dead_code)] // we are not reading the interior of the Entry struct.
+#[derive(Clone, Copy)]
+struct Entry(u64);
+
+impl Entry {
+    const fn new_invalid() -> Self {
+        Entry(0)
+    }
+
+    fn new_stub(vector: usize) -> Self {
+        Self::new(dispatch::stub(vector), 0x08, GateType::Interrupt32Bit, 0)
+    }
+
+    // create a new initialised interrupt table entry:
+    // dpl refers to the cpu priv level which is able to trigger this
+    fn new(isr: usize, segment_selector: u16, gate_type: GateType, dpl: u8) -> Self {
+        // write the isr address
+        let isr_offset = isr as u32;
+        Entry(
+            0u64.set_bits(0, 16, u64::from(isr_offset))
+                .set_bits(48, 16, u64::from(isr_offset >> 16))
+                .set_bits(16, 16, u64::from(segment_selector))
+                .set_bits(40, 4, u64::from(gate_type as u8))
+                .set_bits(45, 2, u64::from(dpl))
+                .set_one_bit(47, true), // enable bit
+        )
+    }
+}
+
+struct InterruptTable {
+    inner: [Entry; IDT_TABLE_SIZE],
+}
+
+impl InterruptTable {
+    const fn new() -> Self {
+        Self {
+            inner: [Entry::new_invalid(); IDT_TABLE_SIZE],
+        }
+    }
+
+    fn set_interrupt(&mut self, entry_id: usize, isr: InterruptServiceRoutine) {
+        let entry = Entry::new(isr as usize, 0x08, GateType::Interrupt32Bit, 0);
+        self.inner[entry_id] = entry;
+    }
+
+    /// For the exceptions where the CPU pushes an error code after the frame
+    fn set_interrupt_with_err(&mut self, entry_id: usize, isr: InterruptServiceRoutineWithErrCode) {
+        let entry = Entry::new(isr as usize, 0x08, GateType::Interrupt32Bit, 0);
+        self.inner[entry_id] = entry;
+    }
+
+    /// Switch to the task behind `tss_selector` instead of calling a handler.
+    fn set_task_gate(&mut self, entry_id: usize, tss_selector: u16) {
+        let entry = Entry::new(0, tss_selector, GateType::Task, 0);
+        self.inner[entry_id] = entry;
+    }
+
+    fn load(&mut self) {
+        // everything without a dedicated handler goes through the dispatcher
+        for i in 0..IDT_TABLE_SIZE {
+            // task gates have no handler address, so go by the present bit
+            if self.inner[i].0.get_bits(47, 1) == 0 {
+                self.inner[i] = Entry::new_stub(i);
+            }
+        }
+
+        let idtr = Idtr {
+            limit: (IDT_TABLE_SIZE * 8 - 1) as u16,
+            base: self.inner.as_ptr() as u32,
+        };
+
+        unsafe {
+            asm!(r#"
+                lidt ({idtr})
+                sti
+            "#, idtr = in (reg) &idtr, options(att_syntax));
+        }
+    }
+}
+
+#[derive(Debug)]
+#[repr(C, packed)]
+struct Idtr {
+    limit: u16,
+    base: u32,
+}
+
+/// Run `f` with interrupts disabled, and restore the interrupt flag after.
+pub fn without_interrupts<R>(f: impl FnOnce() -> R) -> R {
+    let eflags: u32;
+    unsafe {
+        asm!("pushf", "pop {}", "cli", out(reg) eflags, options(att_syntax));
+    }
+
+    let ret = f();
+
+    if eflags & (1 << 9) != 0 {
+        unsafe { asm!("sti", options(nomem, nostack)) };
+    }
+    ret
+}
+
+/// Start delivering `irq` through whichever interrupt controller is in use.
+pub fn unmask_irq(irq: u8) {
+    if apic::is_enabled() {
+        apic::unmask(irq);
+    } else {
+        pic::unmask(irq);
+    }
+}
+
+/// Stop delivering `irq`.
+#[allow(dead_code)]
+pub fn mask_irq(irq: u8) {
+    if apic::is_enabled() {
+        apic::mask(irq);
+    } else {
+        pic::mask(irq);
+    }
+}
+
+pub fn init_idt(palloc: &mut PortAllocator) {
+    println!("old idtr: {:?}", get_idtr());
+
+    pic::init(palloc);
+
+    // load IDT
+    unsafe {
+        let t = (&raw mut INTERRUPT_TABLE)
+            .as_mut()
+            .expect("interrupt table is free");
+
+        exceptions::install(t);
+        double_fault::install(t);
+        page_fault::install(t);
+        t.load();
+    }
+
+    println!("new idtr: {:?}", get_idtr());
+}
+
+fn get_idtr() -> Idtr {
+    let mut r = core::mem::MaybeUninit::uninit();
+    unsafe {
+        asm!("sidt ({})", in (reg) r.as_mut_ptr(), options(att_syntax, nostack, preserves_flags));
+        r.assume_init()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::pit;
+
+    use core::sync::atomic::{AtomicU32, Ordering};
+
+    // not an IRQ, so nothing needs to be acknowledged
+    const TEST_VECTOR: u8 = 0x80;
+
+    static HITS: AtomicU32 = AtomicU32::new(0);
+
+    fn count_hit(context: &mut InterruptContext) {
+        assert_eq!(context.vector, u32::from(TEST_VECTOR));
+        HITS.fetch_add(1, Ordering::Relaxed);
+    }
+
+    #[test_case]
+    fn idt_is_loaded() {
+        let idtr = get_idtr();
+        let table = unsafe { (&raw const INTERRUPT_TABLE).as_ref() }.expect("table is static");
+        assert_eq!({ idtr.limit }, (IDT_TABLE_SIZE * 8 - 1) as u16);
+        assert_eq!({ idtr.base }, table.inner.as_ptr() as u32);
+    }
+
+    #[test_case]
+    fn breakpoint_returns() {
+        unsafe { asm!("int3", options(nomem, nostack)) };
+    }
+
+    #[test_case]
+    fn software_interrupt_reaches_handler() {
+        register_handler(TEST_VECTOR, count_hit).expect("test vector is free");
+        let before = HITS.load(Ordering::Relaxed);
+        unsafe { asm!("int ${}", const TEST_VECTOR, options(att_syntax, nomem, nostack)) };
+        unregister_handler(TEST_VECTOR);
+        assert_eq!(HITS.load(Ordering::Relaxed), before + 1);
+    }
+
+    #[test_case]
+    fn handlers_cannot_be_replaced() {
+        register_handler(TEST_VECTOR, count_hit).expect("test vector is free");
+        assert!(register_handler(TEST_VECTOR, count_hit).is_err());
+        unregister_handler(TEST_VECTOR);
+        assert!(register_handler(3, count_hit).is_err());
+    }
+
+    #[test_case]
+    fn timer_interrupts_arrive() {
+        let before = pit::ticks();
+        pit::sleep_ms(10);
+        assert!(pit::ticks() >= before + 10);
+    }
+
+    #[test_case]
+    fn without_interrupts_restores_flag() {
+        const IF: u32 = 1 << 9;
+        let eflags = || {
+            let flags: u32;
+            unsafe { asm!("pushf", "pop {}", out(reg) flags, options(nomem, preserves_flags)) };
+            flags
+        };
+
+        assert_ne!(eflags() & IF, 0);
+        without_interrupts(|| assert_eq!(eflags() & IF, 0));
+        assert_ne!(eflags() & IF, 0);
+    }
+}
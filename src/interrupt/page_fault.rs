@@ -0,0 +1,199 @@
+// Page fault handling.
+//
+// The handler only gathers what the CPU reports, the faulting address from
+// CR2 and the error code, and leaves the decision to a policy function.
+// The policy can fix the fault (e.g. by mapping a page on demand) so the
+// faulting instruction is retried, ask for the faulting task to be killed,
+// or declare the fault fatal.
+
+use super::{InterruptStackFrame, InterruptTable};
+use crate::paging::{self, PAGE_SIZE, PageFlags};
+use crate::utils::mutex::SpinMutex;
+
+use core::arch::asm;
+use core::fmt;
+
+/// Page fault error code, as pushed by the CPU.
+#[derive(Clone, Copy)]
+pub struct PageFaultError(u32);
+
+#[allow(dead_code)]
+impl PageFaultError {
+    const PRESENT: u32 = 1 << 0;
+    const WRITE: u32 = 1 << 1;
+    const USER: u32 = 1 << 2;
+    const RESERVED_BIT: u32 = 1 << 3;
+    const INSTRUCTION_FETCH: u32 = 1 << 4;
+
+    /// The page was present, so this is a protection violation rather than
+    /// a missing mapping.
+    pub fn is_protection_violation(self) -> bool {
+        self.0 & Self::PRESENT != 0
+    }
+
+    pub fn is_write(self) -> bool {
+        self.0 & Self::WRITE != 0
+    }
+
+    pub fn is_user(self) -> bool {
+        self.0 & Self::USER != 0
+    }
+
+    /// A reserved bit was set in a paging structure entry.
+    pub fn is_reserved_bit(self) -> bool {
+        self.0 & Self::RESERVED_BIT != 0
+    }
+
+    pub fn is_instruction_fetch(self) -> bool {
+        self.0 & Self::INSTRUCTION_FETCH != 0
+    }
+}
+
+impl fmt::Display for PageFaultError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let access = if self.is_instruction_fetch() {
+            "instruction fetch from"
+        } else if self.is_write() {
+            "write to"
+        } else {
+            "read from"
+        };
+        let page = if self.is_protection_violation() {
+            "protected page"
+        } else {
+            "non-present page"
+        };
+        let mode = if self.is_user() { "user" } else { "kernel" };
+
+        write!(f, "{} {} in {} mode", access, page, mode)?;
+        if self.is_reserved_bit() {
+            write!(f, ", reserved bit set in page table entry")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Everything known about one page fault.
+pub struct PageFault {
+    pub address: usize,
+    pub error: PageFaultError,
+    pub frame: InterruptStackFrame,
+}
+
+impl fmt::Display for PageFault {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "EXCEPTION 14: page fault (#PF) at address {:#010x}, error code {:#x} [{}]",
+            self.address, self.error.0, self.error
+        )?;
+
+        if paging::stack_guard().contains(&self.address) {
+            write!(f, ", kernel stack overflow")?;
+        }
+
+        write!(
+            f,
+            "\n  at eip {:#010x}, cs {:#06x}, eflags {:#010x}",
+            self.frame.eip, self.frame.cs, self.frame.eflags
+        )
+    }
+}
+
+/// What to do about a page fault, as decided by the policy.
+#[allow(dead_code)]
+pub enum FaultAction {
+    /// The cause was dealt with, retry the faulting instruction.
+    Resolved,
+    /// The fault is the fault of the running task, which has to go.
+    KillTask,
+    /// The fault can't be recovered from.
+    Panic,
+}
+
+pub type PageFaultPolicy = fn(&PageFault) -> FaultAction;
+
+static POLICY: SpinMutex<PageFaultPolicy> = SpinMutex::new(default_policy);
+
+/// Use `policy` to decide about page faults from now on.
+#[allow(dead_code)]
+pub fn set_policy(policy: PageFaultPolicy) {
+    *POLICY.lock() = policy;
+}
+
+/// Faults from user mode are the task's problem, in the kernel every page
+/// fault is a bug.
+fn default_policy(fault: &PageFault) -> FaultAction {
+    if fault.error.is_user() {
+        FaultAction::KillTask
+    } else {
+        FaultAction::Panic
+    }
+}
+
+/// Back the page containing the faulting address with a fresh zeroed frame.
+///
+/// A building block for policies. Protection violations are not something
+/// a new mapping can fix, and the null page and the stack guard are unmapped
+/// on purpose, those are left to panic. Faults from user mode get a user
+/// page. Takes the frame allocator lock, so faults from code holding it
+/// can't be handled this way.
+#[allow(dead_code)]
+pub fn demand_map(fault: &PageFault) -> FaultAction {
+    if fault.error.is_protection_violation() {
+        return FaultAction::Panic;
+    }
+
+    let page = fault.address & !(PAGE_SIZE - 1);
+    if page == 0 || paging::stack_guard().contains(&page) {
+        return FaultAction::Panic;
+    }
+
+    let flags = if fault.error.is_user() {
+        PageFlags::WRITABLE | PageFlags::USER
+    } else {
+        PageFlags::WRITABLE
+    };
+
+    let mut frames = crate::FRAMES.lock();
+    let Some(frame) = frames.allocate() else {
+        return FaultAction::Panic;
+    };
+
+    if unsafe { paging::map(page, frame, flags, &mut frames) }.is_err() {
+        frames.deallocate(frame);
+        return FaultAction::Panic;
+    }
+
+    unsafe { (page as *mut u8).write_bytes(0, PAGE_SIZE) };
+    FaultAction::Resolved
+}
+
+fn read_cr2() -> usize {
+    let address: usize;
+    unsafe {
+        asm!("mov %cr2, {}", out(reg) address, options(att_syntax, nomem, nostack, preserves_flags));
+    }
+    address
+}
+
+extern "x86-interrupt" fn page_fault_handler(frame: InterruptStackFrame, error_code: u32) {
+    let fault = PageFault {
+        address: read_cr2(),
+        error: PageFaultError(error_code),
+        frame,
+    };
+
+    let policy = *POLICY.lock();
+    match policy(&fault) {
+        FaultAction::Resolved => {}
+        // there is no scheduler to remove the task from yet
+        FaultAction::KillTask => panic!("{}\n  no task to kill, giving up", fault),
+        FaultAction::Panic => panic!("{}", fault),
+    }
+}
+
+pub(super) fn install(table: &mut InterruptTable) {
+    table.set_interrupt_with_err(14, page_fault_handler);
+}
@@ -0,0 +1,215 @@
+// Driver for the pair of cascaded 8259 programmable interrupt controllers.
+//
+// The master handles IRQs 0 to 7 and the slave, which is wired to the
+// master's IRQ 2, handles IRQs 8 to 15. Both are remapped past the CPU
+// exception vectors, and every line starts out masked so that drivers only
+// get the lines they ask for.
+//
+// See: https://wiki.osdev.org/8259_PIC
+
+use super::without_interrupts;
+use crate::io::ports::{Port, PortAllocator};
+use crate::utils::mutex::SpinMutex;
+
+/// Vector of IRQ 0, the master's lines follow.
+pub const MASTER_OFFSET: u8 = 0x20;
+/// Vector of IRQ 8, the slave's lines follow.
+pub const SLAVE_OFFSET: u8 = 0x28;
+
+/// The master line the slave is wired to.
+pub const CASCADE_IRQ: u8 = 2;
+
+const ICW1_INIT: u8 = 0x10;
+const ICW1_ICW4: u8 = 0x01;
+const ICW4_8086: u8 = 0x01;
+const OCW2_EOI: u8 = 0x20;
+const OCW3_READ_IRR: u8 = 0x0a;
+const OCW3_READ_ISR: u8 = 0x0b;
+
+static PIC: SpinMutex<Option<Pic>> = SpinMutex::new(None);
+
+/// The vector `irq` arrives at.
+pub const fn irq_vector(irq: u8) -> u8 {
+    MASTER_OFFSET + irq
+}
+
+/// The IRQ line behind `vector`, if it belongs to the PICs.
+pub const fn vector_irq(vector: u8) -> Option<u8> {
+    if vector >= MASTER_OFFSET && vector < SLAVE_OFFSET + 8 {
+        Some(vector - MASTER_OFFSET)
+    } else {
+        None
+    }
+}
+
+struct Chip {
+    command: Port,
+    data: Port,
+}
+
+impl Chip {
+    fn new(palloc: &mut PortAllocator, base: u16) -> Result<Self, &'static str> {
+        let command = palloc.allocate(base).ok_or("PIC command port in use")?;
+        let data = palloc.allocate(base + 1).ok_or("PIC data port in use")?;
+        Ok(Chip { command, data })
+    }
+
+    fn read_register(&mut self, ocw3: u8) -> u8 {
+        self.command.outb(ocw3);
+        self.command.inb()
+    }
+}
+
+pub struct Pic {
+    master: Chip,
+    slave: Chip,
+}
+
+#[allow(dead_code)] // read_irr is for debugging
+impl Pic {
+    pub fn new(palloc: &mut PortAllocator) -> Result<Self, &'static str> {
+        Ok(Pic {
+            master: Chip::new(palloc, 0x20)?,
+            slave: Chip::new(palloc, 0xA0)?,
+        })
+    }
+
+    /// Remap both chips and mask every line except the cascade.
+    pub fn init(&mut self) {
+        self.master.command.outb(ICW1_INIT | ICW1_ICW4);
+        self.slave.command.outb(ICW1_INIT | ICW1_ICW4);
+
+        self.master.data.outb(MASTER_OFFSET);
+        self.slave.data.outb(SLAVE_OFFSET);
+
+        self.master.data.outb(1 << CASCADE_IRQ); // slave sits on IRQ 2
+        self.slave.data.outb(CASCADE_IRQ); // cascade identity
+
+        self.master.data.outb(ICW4_8086);
+        self.slave.data.outb(ICW4_8086);
+
+        self.master.data.outb(!(1 << CASCADE_IRQ));
+        self.slave.data.outb(0xff);
+    }
+
+    fn chip_for(&mut self, irq: u8) -> (&mut Chip, u8) {
+        if irq < 8 {
+            (&mut self.master, irq)
+        } else {
+            (&mut self.slave, irq - 8)
+        }
+    }
+
+    pub fn mask(&mut self, irq: u8) {
+        let (chip, line) = self.chip_for(irq);
+        let mask = chip.data.inb();
+        chip.data.outb(mask | 1 << line);
+    }
+
+    pub fn unmask(&mut self, irq: u8) {
+        let (chip, line) = self.chip_for(irq);
+        let mask = chip.data.inb();
+        chip.data.outb(mask & !(1 << line));
+    }
+
+    /// Mask every line.
+    pub fn disable(&mut self) {
+        self.master.data.outb(0xff);
+        self.slave.data.outb(0xff);
+    }
+
+    /// Masked lines, slave in the high byte.
+    pub fn masks(&mut self) -> u16 {
+        u16::from_le_bytes([self.master.data.inb(), self.slave.data.inb()])
+    }
+
+    /// In-service register: IRQs being handled, slave in the high byte.
+    pub fn read_isr(&mut self) -> u16 {
+        u16::from_le_bytes([
+            self.master.read_register(OCW3_READ_ISR),
+            self.slave.read_register(OCW3_READ_ISR),
+        ])
+    }
+
+    /// Interrupt request register: IRQs raised but not yet delivered, slave
+    /// in the high byte.
+    pub fn read_irr(&mut self) -> u16 {
+        u16::from_le_bytes([
+            self.master.read_register(OCW3_READ_IRR),
+            self.slave.read_register(OCW3_READ_IRR),
+        ])
+    }
+
+    /// Whether `irq` was a spurious interrupt.
+    ///
+    /// When a line drops before the CPU acknowledges it, the chip delivers
+    /// its lowest priority line (7 on either chip) without setting the
+    /// corresponding in-service bit. Those must not be acknowledged, except
+    /// that the master did see a real interrupt on the cascade line for a
+    /// spurious IRQ 15, which this takes care of.
+    pub fn is_spurious(&mut self, irq: u8) -> bool {
+        if irq != 7 && irq != 15 {
+            return false;
+        }
+
+        if self.read_isr() & (1 << irq) != 0 {
+            return false;
+        }
+
+        if irq == 15 {
+            self.master.command.outb(OCW2_EOI);
+        }
+        true
+    }
+
+    pub fn end_of_interrupt(&mut self, irq: u8) {
+        if irq >= 8 {
+            self.slave.command.outb(OCW2_EOI);
+        }
+        self.master.command.outb(OCW2_EOI);
+    }
+}
+
+fn with_pic<R>(f: impl FnOnce(&mut Pic) -> R) -> R {
+    // interrupt handlers take the lock too
+    without_interrupts(|| f(PIC.lock().as_mut().expect("PIC is initialised")))
+}
+
+/// Set up the PICs with every line masked.
+pub fn init(palloc: &mut PortAllocator) {
+    let mut pic = Pic::new(palloc).expect("PIC ports are free");
+    pic.init();
+    *PIC.lock() = Some(pic);
+}
+
+/// Stop delivering `irq`.
+#[allow(dead_code)]
+pub fn mask(irq: u8) {
+    with_pic(|pic| pic.mask(irq));
+}
+
+/// Start delivering `irq`. Drivers go through `interrupt::unmask_irq`,
+/// which knows whether the 8259s are in use at all.
+pub fn unmask(irq: u8) {
+    with_pic(|pic| pic.unmask(irq));
+}
+
+/// Mask every line for good, when the APICs take over. Returns the lines
+/// that were masked before.
+pub fn disable() -> u16 {
+    with_pic(|pic| {
+        let masks = pic.masks();
+        pic.disable();
+        masks
+    })
+}
+
+/// Called by the dispatcher before running the handler for `irq`.
+pub(super) fn is_spurious(irq: u8) -> bool {
+    with_pic(|pic| pic.is_spurious(irq))
+}
+
+/// Called by the dispatcher after running the handler for `irq`.
+pub(super) fn end_of_interrupt(irq: u8) {
+    with_pic(|pic| pic.end_of_interrupt(irq));
+}
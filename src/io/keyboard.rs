@@ -0,0 +1,536 @@
+// PS/2 keyboard driver.
+//
+// The controller hands over one byte of scancode set 1 per IRQ 1. A key
+// press is its make code, a release the make code with bit 7 set. Keys that
+// were added after the original XT keyboard send 0xe0 first, and Pause sends
+// a six byte 0xe1 sequence with no release at all.
+//
+// Scancodes are decoded in the interrupt handler, with the modifier state
+// kept there too, and the resulting events are pushed into a lock-free
+// queue for normal code to read. `io::ps2` sets up the controller, with
+// translation to set 1 on.
+//
+// See: https://wiki.osdev.org/PS/2_Keyboard
+
+use crate::interrupt::{self, InterruptContext, pic};
+use crate::io::ps2::{self, Channel};
+use crate::utils::mutex::SpinMutex;
+use crate::utils::spsc::SpscQueue;
+
+use core::ops::BitOr;
+
+const IRQ: u8 = 1;
+
+const EXTENDED: u8 = 0xe0;
+const PAUSE: u8 = 0xe1;
+const PAUSE_LEN: u8 = 6;
+const RELEASED: u8 = 0x80;
+
+const QUEUE_SIZE: usize = 64;
+
+/// A physical key, named after its US layout legend.
+#[allow(dead_code)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum KeyCode {
+    Escape,
+    F1,
+    F2,
+    F3,
+    F4,
+    F5,
+    F6,
+    F7,
+    F8,
+    F9,
+    F10,
+    F11,
+    F12,
+    Backtick,
+    Num1,
+    Num2,
+    Num3,
+    Num4,
+    Num5,
+    Num6,
+    Num7,
+    Num8,
+    Num9,
+    Num0,
+    Minus,
+    Equals,
+    Backspace,
+    Tab,
+    Q,
+    W,
+    E,
+    R,
+    T,
+    Y,
+    U,
+    I,
+    O,
+    P,
+    LeftBracket,
+    RightBracket,
+    Backslash,
+    CapsLock,
+    A,
+    S,
+    D,
+    F,
+    G,
+    H,
+    J,
+    K,
+    L,
+    Semicolon,
+    Quote,
+    Enter,
+    LeftShift,
+    Z,
+    X,
+    C,
+    V,
+    B,
+    N,
+    M,
+    Comma,
+    Period,
+    Slash,
+    RightShift,
+    LeftCtrl,
+    LeftGui,
+    LeftAlt,
+    Space,
+    RightAlt,
+    RightGui,
+    Menu,
+    RightCtrl,
+    Insert,
+    Delete,
+    Home,
+    End,
+    PageUp,
+    PageDown,
+    Up,
+    Down,
+    Left,
+    Right,
+    NumLock,
+    ScrollLock,
+    Pause,
+    KeypadSlash,
+    KeypadStar,
+    KeypadMinus,
+    KeypadPlus,
+    KeypadEnter,
+    KeypadPeriod,
+    Keypad0,
+    Keypad1,
+    Keypad2,
+    Keypad3,
+    Keypad4,
+    Keypad5,
+    Keypad6,
+    Keypad7,
+    Keypad8,
+    Keypad9,
+}
+
+/// Modifier keys held down, and lock keys switched on.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct Modifiers(u8);
+
+#[allow(dead_code)]
+impl Modifiers {
+    pub const SHIFT: Self = Self(1 << 0);
+    pub const CTRL: Self = Self(1 << 1);
+    pub const ALT: Self = Self(1 << 2);
+    pub const CAPS_LOCK: Self = Self(1 << 3);
+    pub const NUM_LOCK: Self = Self(1 << 4);
+
+    pub const fn empty() -> Self {
+        Self(0)
+    }
+
+    pub const fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    fn set(&mut self, other: Self, enable: bool) {
+        if enable {
+            self.0 |= other.0;
+        } else {
+            self.0 &= !other.0;
+        }
+    }
+
+    fn toggle(&mut self, other: Self) {
+        self.0 ^= other.0;
+    }
+}
+
+impl BitOr for Modifiers {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+
+#[allow(dead_code)] // only the character is used so far
+#[derive(Clone, Copy, Debug)]
+pub struct KeyEvent {
+    pub code: KeyCode,
+    pub pressed: bool,
+    /// Modifiers after this event was applied.
+    pub modifiers: Modifiers,
+    /// What the key types on a US layout, for presses only.
+    pub ch: Option<char>,
+}
+
+fn set1(code: u8) -> Option<KeyCode> {
+    use KeyCode::*;
+    const TABLE: [Option<KeyCode>; 0x59] = [
+        None,
+        Some(Escape),
+        Some(Num1),
+        Some(Num2),
+        Some(Num3),
+        Some(Num4),
+        Some(Num5),
+        Some(Num6),
+        Some(Num7),
+        Some(Num8),
+        Some(Num9),
+        Some(Num0),
+        Some(Minus),
+        Some(Equals),
+        Some(Backspace),
+        Some(Tab),
+        Some(Q),
+        Some(W),
+        Some(E),
+        Some(R),
+        Some(T),
+        Some(Y),
+        Some(U),
+        Some(I),
+        Some(O),
+        Some(P),
+        Some(LeftBracket),
+        Some(RightBracket),
+        Some(Enter),
+        Some(LeftCtrl),
+        Some(A),
+        Some(S),
+        Some(D),
+        Some(F),
+        Some(G),
+        Some(H),
+        Some(J),
+        Some(K),
+        Some(L),
+        Some(Semicolon),
+        Some(Quote),
+        Some(Backtick),
+        Some(LeftShift),
+        Some(Backslash),
+        Some(Z),
+        Some(X),
+        Some(C),
+        Some(V),
+        Some(B),
+        Some(N),
+        Some(M),
+        Some(Comma),
+        Some(Period),
+        Some(Slash),
+        Some(RightShift),
+        Some(KeypadStar),
+        Some(LeftAlt),
+        Some(Space),
+        Some(CapsLock),
+        Some(F1),
+        Some(F2),
+        Some(F3),
+        Some(F4),
+        Some(F5),
+        Some(F6),
+        Some(F7),
+        Some(F8),
+        Some(F9),
+        Some(F10),
+        Some(NumLock),
+        Some(ScrollLock),
+        Some(Keypad7),
+        Some(Keypad8),
+        Some(Keypad9),
+        Some(KeypadMinus),
+        Some(Keypad4),
+        Some(Keypad5),
+        Some(Keypad6),
+        Some(KeypadPlus),
+        Some(Keypad1),
+        Some(Keypad2),
+        Some(Keypad3),
+        Some(Keypad0),
+        Some(KeypadPeriod),
+        None,
+        None,
+        None,
+        Some(F11),
+        Some(F12),
+    ];
+
+    TABLE.get(code as usize).copied().flatten()
+}
+
+fn set1_extended(code: u8) -> Option<KeyCode> {
+    use KeyCode::*;
+    Some(match code {
+        0x1c => KeypadEnter,
+        0x1d => RightCtrl,
+        0x35 => KeypadSlash,
+        0x38 => RightAlt,
+        0x47 => Home,
+        0x48 => Up,
+        0x49 => PageUp,
+        0x4b => Left,
+        0x4d => Right,
+        0x4f => End,
+        0x50 => Down,
+        0x51 => PageDown,
+        0x52 => Insert,
+        0x53 => Delete,
+        0x5b => LeftGui,
+        0x5c => RightGui,
+        0x5d => Menu,
+        // 0x2a and 0x37 are the fake shift and Print Screen, ignored
+        _ => return None,
+    })
+}
+
+/// Characters a key types on a US layout, without and with shift.
+fn us_layout(code: KeyCode) -> Option<(char, char)> {
+    use KeyCode::*;
+    Some(match code {
+        Backtick => ('`', '~'),
+        Num1 => ('1', '!'),
+        Num2 => ('2', '@'),
+        Num3 => ('3', '#'),
+        Num4 => ('4', '$'),
+        Num5 => ('5', '%'),
+        Num6 => ('6', '^'),
+        Num7 => ('7', '&'),
+        Num8 => ('8', '*'),
+        Num9 => ('9', '('),
+        Num0 => ('0', ')'),
+        Minus => ('-', '_'),
+        Equals => ('=', '+'),
+        Backspace => ('\x08', '\x08'),
+        Tab => ('\t', '\t'),
+        Q => ('q', 'Q'),
+        W => ('w', 'W'),
+        E => ('e', 'E'),
+        R => ('r', 'R'),
+        T => ('t', 'T'),
+        Y => ('y', 'Y'),
+        U => ('u', 'U'),
+        I => ('i', 'I'),
+        O => ('o', 'O'),
+        P => ('p', 'P'),
+        LeftBracket => ('[', '{'),
+        RightBracket => (']', '}'),
+        Backslash => ('\\', '|'),
+        A => ('a', 'A'),
+        S => ('s', 'S'),
+        D => ('d', 'D'),
+        F => ('f', 'F'),
+        G => ('g', 'G'),
+        H => ('h', 'H'),
+        J => ('j', 'J'),
+        K => ('k', 'K'),
+        L => ('l', 'L'),
+        Semicolon => (';', ':'),
+        Quote => ('\'', '"'),
+        Enter | KeypadEnter => ('\n', '\n'),
+        Z => ('z', 'Z'),
+        X => ('x', 'X'),
+        C => ('c', 'C'),
+        V => ('v', 'V'),
+        B => ('b', 'B'),
+        N => ('n', 'N'),
+        M => ('m', 'M'),
+        Comma => (',', '<'),
+        Period => ('.', '>'),
+        Slash => ('/', '?'),
+        Space => (' ', ' '),
+        KeypadSlash => ('/', '/'),
+        KeypadStar => ('*', '*'),
+        KeypadMinus => ('-', '-'),
+        KeypadPlus => ('+', '+'),
+        _ => return None,
+    })
+}
+
+/// What the keypad's number keys type with num lock on.
+fn keypad_digit(code: KeyCode) -> Option<char> {
+    use KeyCode::*;
+    Some(match code {
+        Keypad0 => '0',
+        Keypad1 => '1',
+        Keypad2 => '2',
+        Keypad3 => '3',
+        Keypad4 => '4',
+        Keypad5 => '5',
+        Keypad6 => '6',
+        Keypad7 => '7',
+        Keypad8 => '8',
+        Keypad9 => '9',
+        KeypadPeriod => '.',
+        _ => return None,
+    })
+}
+
+fn is_letter(code: KeyCode) -> bool {
+    us_layout(code).is_some_and(|(c, _)| c.is_ascii_lowercase())
+}
+
+/// Scancode set 1 decoder, fed one byte at a time.
+struct Decoder {
+    extended: bool,
+    pause_left: u8,
+    // modifier keys are tracked per side, so releasing one shift while the
+    // other is held keeps shift on
+    shift: [bool; 2],
+    ctrl: [bool; 2],
+    alt: [bool; 2],
+    modifiers: Modifiers,
+}
+
+impl Decoder {
+    const fn new() -> Self {
+        Decoder {
+            extended: false,
+            pause_left: 0,
+            shift: [false; 2],
+            ctrl: [false; 2],
+            alt: [false; 2],
+            modifiers: Modifiers::empty(),
+        }
+    }
+
+    fn feed(&mut self, byte: u8) -> Option<KeyEvent> {
+        if self.pause_left > 0 {
+            self.pause_left -= 1;
+            return None;
+        }
+
+        match byte {
+            EXTENDED => {
+                self.extended = true;
+                return None;
+            }
+            PAUSE => {
+                self.pause_left = PAUSE_LEN - 1;
+                return Some(KeyEvent {
+                    code: KeyCode::Pause,
+                    pressed: true,
+                    modifiers: self.modifiers,
+                    ch: None,
+                });
+            }
+            _ => {}
+        }
+
+        let extended = core::mem::take(&mut self.extended);
+        let pressed = byte & RELEASED == 0;
+        let make = byte & !RELEASED;
+        let code = if extended {
+            set1_extended(make)
+        } else {
+            set1(make)
+        }?;
+
+        self.update_modifiers(code, pressed);
+
+        Some(KeyEvent {
+            code,
+            pressed,
+            modifiers: self.modifiers,
+            ch: if pressed { self.translate(code) } else { None },
+        })
+    }
+
+    fn update_modifiers(&mut self, code: KeyCode, pressed: bool) {
+        use KeyCode::*;
+        match code {
+            LeftShift => self.shift[0] = pressed,
+            RightShift => self.shift[1] = pressed,
+            LeftCtrl => self.ctrl[0] = pressed,
+            RightCtrl => self.ctrl[1] = pressed,
+            LeftAlt => self.alt[0] = pressed,
+            RightAlt => self.alt[1] = pressed,
+            CapsLock if pressed => self.modifiers.toggle(Modifiers::CAPS_LOCK),
+            NumLock if pressed => self.modifiers.toggle(Modifiers::NUM_LOCK),
+            _ => return,
+        }
+
+        let any = |sides: [bool; 2]| sides[0] || sides[1];
+        self.modifiers.set(Modifiers::SHIFT, any(self.shift));
+        self.modifiers.set(Modifiers::CTRL, any(self.ctrl));
+        self.modifiers.set(Modifiers::ALT, any(self.alt));
+    }
+
+    fn translate(&self, code: KeyCode) -> Option<char> {
+        if self.modifiers.contains(Modifiers::NUM_LOCK) {
+            if let Some(ch) = keypad_digit(code) {
+                return Some(ch);
+            }
+        }
+
+        let (normal, shifted) = us_layout(code)?;
+        let mut shift = self.modifiers.contains(Modifiers::SHIFT);
+        if is_letter(code) && self.modifiers.contains(Modifiers::CAPS_LOCK) {
+            shift = !shift;
+        }
+
+        Some(if shift { shifted } else { normal })
+    }
+}
+
+// only touched by the interrupt handler
+static DECODER: SpinMutex<Decoder> = SpinMutex::new(Decoder::new());
+static EVENTS: SpscQueue<KeyEvent, QUEUE_SIZE> = SpscQueue::new();
+
+pub fn init() -> Result<(), &'static str> {
+    interrupt::register_handler(pic::irq_vector(IRQ), keyboard_interrupt)?;
+    interrupt::unmask_irq(IRQ);
+    Ok(())
+}
+
+/// Next key event, if any arrived.
+pub fn read_event() -> Option<KeyEvent> {
+    EVENTS.pop()
+}
+
+/// Next typed character, skipping releases and keys that don't type.
+pub fn read_char() -> Option<char> {
+    while let Some(event) = read_event() {
+        if let Some(ch) = event.ch {
+            return Some(ch);
+        }
+    }
+    None
+}
+
+fn keyboard_interrupt(_context: &mut InterruptContext) {
+    let Some(byte) = ps2::irq_byte(Channel::First) else {
+        return;
+    };
+    if let Some(event) = DECODER.lock().feed(byte) {
+        // nobody is reading, dropping new keys is all that can be done
+        let _ = EVENTS.push(event);
+    }
+}
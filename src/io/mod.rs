@@ -1,2 +1,8 @@
+pub mod keyboard;
+pub mod mouse;
+pub mod pit;
 pub mod ports;
+pub mod ps2;
+pub mod rtc;
+pub mod serial;
 pub mod vga;
@@ -0,0 +1,187 @@
+// PS/2 mouse driver, for the device on the controller's second port.
+//
+// Once streaming is on, the mouse sends a packet for every movement or
+// button change, one byte per IRQ 12:
+//
+//   byte 0: buttons, a bit that is always set, sign and overflow bits
+//   byte 1: X movement, low 8 bits of a 9-bit two's complement value
+//   byte 2: Y movement, same, positive is up
+//   byte 3: wheel movement, only on IntelliMouse compatible mice
+//
+// A mouse only sends the fourth byte after the "magic knock": setting the
+// sample rate to 200, 100 and 80 in a row makes it report device ID 3.
+// Packets are decoded in the interrupt handler and pushed into a lock-free
+// queue for normal code to read, like the keyboard's events.
+//
+// See: https://wiki.osdev.org/PS/2_Mouse
+
+use crate::info;
+use crate::interrupt::{self, InterruptContext, pic};
+use crate::io::ps2::{self, Channel, Ps2Error};
+use crate::utils::mutex::SpinMutex;
+use crate::utils::spsc::SpscQueue;
+
+const IRQ: u8 = 12;
+
+const SET_DEFAULTS: u8 = 0xf6;
+const ENABLE_STREAMING: u8 = 0xf4;
+const SET_SAMPLE_RATE: u8 = 0xf3;
+const GET_ID: u8 = 0xf2;
+
+const ID_WHEEL: u8 = 3;
+const WHEEL_KNOCK: [u8; 3] = [200, 100, 80];
+const SAMPLE_RATE: u8 = 100;
+
+const LEFT: u8 = 1 << 0;
+const RIGHT: u8 = 1 << 1;
+const MIDDLE: u8 = 1 << 2;
+const ALWAYS_SET: u8 = 1 << 3;
+const X_SIGN: u8 = 1 << 4;
+const Y_SIGN: u8 = 1 << 5;
+const X_OVERFLOW: u8 = 1 << 6;
+const Y_OVERFLOW: u8 = 1 << 7;
+
+const QUEUE_SIZE: usize = 64;
+
+#[allow(dead_code)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct Buttons {
+    pub left: bool,
+    pub right: bool,
+    pub middle: bool,
+}
+
+#[allow(dead_code)]
+#[derive(Clone, Copy, Debug)]
+pub struct MouseEvent {
+    /// Movement since the last event, positive is right.
+    pub dx: i16,
+    /// Movement since the last event, positive is up.
+    pub dy: i16,
+    /// Wheel movement, positive is towards the user. Always 0 without a
+    /// wheel.
+    pub wheel: i8,
+    /// Buttons held down now.
+    pub buttons: Buttons,
+}
+
+// 9-bit two's complement, the sign bit lives in the first byte
+fn movement(low: u8, negative: bool) -> i16 {
+    if negative {
+        i16::from(low) - 0x100
+    } else {
+        i16::from(low)
+    }
+}
+
+fn decode(packet: &[u8]) -> Option<MouseEvent> {
+    let flags = packet[0];
+    // the counters wrapped, the movement is garbage
+    if flags & (X_OVERFLOW | Y_OVERFLOW) != 0 {
+        return None;
+    }
+
+    Some(MouseEvent {
+        dx: movement(packet[1], flags & X_SIGN != 0),
+        dy: movement(packet[2], flags & Y_SIGN != 0),
+        wheel: packet.get(3).map_or(0, |&z| z as i8),
+        buttons: Buttons {
+            left: flags & LEFT != 0,
+            right: flags & RIGHT != 0,
+            middle: flags & MIDDLE != 0,
+        },
+    })
+}
+
+/// Gathers bytes into packets.
+struct Assembler {
+    packet: [u8; 4],
+    len: usize,
+    packet_len: usize,
+}
+
+impl Assembler {
+    const fn new() -> Self {
+        Assembler {
+            packet: [0; 4],
+            len: 0,
+            packet_len: 3,
+        }
+    }
+
+    fn feed(&mut self, byte: u8) -> Option<MouseEvent> {
+        // a first byte always has bit 3 set, drop bytes until one does to
+        // get back in sync after a lost byte
+        if self.len == 0 && byte & ALWAYS_SET == 0 {
+            return None;
+        }
+
+        self.packet[self.len] = byte;
+        self.len += 1;
+        if self.len < self.packet_len {
+            return None;
+        }
+
+        self.len = 0;
+        decode(&self.packet[..self.packet_len])
+    }
+}
+
+// only touched by the interrupt handler, after init
+static ASSEMBLER: SpinMutex<Assembler> = SpinMutex::new(Assembler::new());
+static EVENTS: SpscQueue<MouseEvent, QUEUE_SIZE> = SpscQueue::new();
+
+fn set_sample_rate(rate: u8) -> Result<(), Ps2Error> {
+    ps2::device_command(Channel::Second, SET_SAMPLE_RATE)?;
+    ps2::device_command(Channel::Second, rate)
+}
+
+fn device_id() -> Result<u8, Ps2Error> {
+    ps2::device_command(Channel::Second, GET_ID)?;
+    ps2::read_data()
+}
+
+/// Set up the mouse on the second PS/2 port, which `ps2::init` must have
+/// found working, and start taking packets on IRQ 12.
+pub fn init() -> Result<(), Ps2Error> {
+    if !ps2::is_working(Channel::Second) {
+        return Err(Ps2Error::NoPort(Channel::Second));
+    }
+
+    ps2::device_command(Channel::Second, SET_DEFAULTS)?;
+
+    for rate in WHEEL_KNOCK {
+        set_sample_rate(rate)?;
+    }
+    let wheel = device_id()? == ID_WHEEL;
+    set_sample_rate(SAMPLE_RATE)?;
+    if wheel {
+        ASSEMBLER.lock().packet_len = 4;
+    }
+
+    let vector = pic::irq_vector(IRQ);
+    interrupt::register_handler(vector, mouse_interrupt)
+        .map_err(|_| Ps2Error::VectorInUse(vector))?;
+    ps2::device_command(Channel::Second, ENABLE_STREAMING)?;
+    // IRQs raised while masked are still latched, no packet gets lost
+    interrupt::unmask_irq(IRQ);
+
+    info!("{}", if wheel { "wheel mouse" } else { "3 buttons" });
+    Ok(())
+}
+
+/// Next mouse event, if any arrived.
+#[allow(dead_code)]
+pub fn read_event() -> Option<MouseEvent> {
+    EVENTS.pop()
+}
+
+fn mouse_interrupt(_context: &mut InterruptContext) {
+    let Some(byte) = ps2::irq_byte(Channel::Second) else {
+        return;
+    };
+    if let Some(event) = ASSEMBLER.lock().feed(byte) {
+        // nobody is reading, dropping new events is all that can be done
+        let _ = EVENTS.push(event);
+    }
+}
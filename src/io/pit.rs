@@ -0,0 +1,240 @@
+// Driver for channel 0 of the 8253/8254 programmable interval timer.
+//
+// Channel 0 is wired to IRQ 0 and fires at the base frequency divided by a
+// 16-bit divisor. Every interrupt is a tick: the tick count since `init` is
+// the kernel's monotonic clock, and one-shot timers are checked against it
+// on every tick.
+//
+// See: https://wiki.osdev.org/Programmable_Interval_Timer
+
+use crate::interrupt::{self, InterruptContext, pic, without_interrupts};
+use crate::io::ports::{Port, PortAllocator};
+use crate::utils::mutex::SpinMutex;
+
+use core::arch::asm;
+use core::time::Duration;
+
+/// Frequency of the oscillator feeding the PIT, in Hz.
+pub const BASE_FREQUENCY: u32 = 1_193_182;
+
+const IRQ: u8 = 0;
+
+// channel 0, low byte then high byte, mode 3 (square wave), binary
+const CHANNEL0_SQUARE_WAVE: u8 = 0b0011_0110;
+// channel 2, low byte then high byte, mode 0 (one-shot), binary
+const CHANNEL2_ONE_SHOT: u8 = 0b1011_0000;
+
+// port 0x61 bits: channel 2 gate, speaker enable, channel 2 output
+const GATE2: u8 = 1 << 0;
+const SPEAKER: u8 = 1 << 1;
+const OUT2: u8 = 1 << 5;
+
+/// Longest interval channel 2 can measure, the count is 16 bits.
+pub const MAX_MEASURE_MS: u32 = 0xffff * 1000 / BASE_FREQUENCY;
+
+const MAX_TIMERS: usize = 16;
+
+struct Pit {
+    // kept so nothing else can reprogram the timer
+    _channel0: Port,
+    channel2: Port,
+    command: Port,
+    gate: Port,
+    frequency: u32,
+}
+
+static PIT: SpinMutex<Option<Pit>> = SpinMutex::new(None);
+
+// only changed by the IRQ handler; reads happen with interrupts off, a u64
+// can't be read atomically on i686
+static TICKS: SpinMutex<u64> = SpinMutex::new(0);
+
+/// Identifies a pending one-shot timer, see `cancel_timer`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct TimerId(u32);
+
+#[derive(Clone, Copy)]
+struct Timer {
+    id: TimerId,
+    deadline: u64,
+    callback: fn(),
+}
+
+struct Timers {
+    pending: [Option<Timer>; MAX_TIMERS],
+    next_id: u32,
+}
+
+static TIMERS: SpinMutex<Timers> = SpinMutex::new(Timers {
+    pending: [None; MAX_TIMERS],
+    next_id: 0,
+});
+
+/// Program channel 0 to tick at roughly `frequency` Hz and start counting.
+pub fn init(palloc: &mut PortAllocator, frequency: u32) -> Result<(), &'static str> {
+    if frequency == 0 || frequency > BASE_FREQUENCY {
+        return Err("PIT frequency out of range");
+    }
+
+    let mut channel0 = palloc.allocate(0x40).ok_or("PIT channel 0 port in use")?;
+    let channel2 = palloc.allocate(0x42).ok_or("PIT channel 2 port in use")?;
+    let mut command = palloc.allocate(0x43).ok_or("PIT command port in use")?;
+    let gate = palloc
+        .allocate(0x61)
+        .ok_or("PIT channel 2 gate port in use")?;
+
+    // a divisor of 0 means 65536, the slowest the PIT can go
+    let divisor = (BASE_FREQUENCY / frequency).clamp(1, 0x1_0000);
+    command.outb(CHANNEL0_SQUARE_WAVE);
+    channel0.outb(divisor as u8);
+    channel0.outb((divisor >> 8) as u8);
+
+    *PIT.lock() = Some(Pit {
+        _channel0: channel0,
+        channel2,
+        command,
+        gate,
+        frequency: BASE_FREQUENCY / divisor,
+    });
+
+    interrupt::register_handler(pic::irq_vector(IRQ), tick)?;
+    interrupt::unmask_irq(IRQ);
+    Ok(())
+}
+
+/// Actual tick frequency, which is only as close to the requested one as
+/// the divisor allows.
+pub fn frequency() -> u32 {
+    PIT.lock().as_ref().expect("PIT is initialised").frequency
+}
+
+/// Ticks since `init`.
+pub fn ticks() -> u64 {
+    without_interrupts(|| *TICKS.lock())
+}
+
+/// Run channel 2 for `ms` milliseconds and return how far `counter` moved
+/// in that time.
+///
+/// Busy waits on the channel's output with interrupts off instead of
+/// relying on them, so it can be used for calibrating other clocks.
+pub fn measure(ms: u32, counter: impl Fn() -> u64) -> Result<u64, &'static str> {
+    if ms == 0 || ms > MAX_MEASURE_MS {
+        return Err("PIT measurement interval out of range");
+    }
+
+    let count = BASE_FREQUENCY * ms / 1000;
+
+    // an interrupt between the two samples would be counted too
+    without_interrupts(|| {
+        let mut pit = PIT.lock();
+        let pit = pit.as_mut().ok_or("PIT is not initialised")?;
+
+        // gate high starts counting once the count is written, speaker stays
+        // off
+        let gate = pit.gate.inb();
+        pit.gate.outb((gate & !SPEAKER) | GATE2);
+
+        pit.command.outb(CHANNEL2_ONE_SHOT);
+        pit.channel2.outb(count as u8);
+        pit.channel2.outb((count >> 8) as u8);
+
+        let start = counter();
+        while pit.gate.inb() & OUT2 == 0 {}
+        let end = counter();
+
+        pit.gate.outb(gate);
+        Ok(end - start)
+    })
+}
+
+fn ms_to_ticks(ms: u64) -> u64 {
+    // round up, sleeping too short is worse than too long
+    (ms * u64::from(frequency())).div_ceil(1000)
+}
+
+/// Time since `init`.
+#[allow(dead_code)]
+pub fn uptime() -> Duration {
+    let freq = u64::from(frequency());
+    let ticks = ticks();
+    Duration::from_secs(ticks / freq) + Duration::from_nanos((ticks % freq) * 1_000_000_000 / freq)
+}
+
+/// Wait at least `ms` milliseconds. Interrupts have to be on.
+#[allow(dead_code)]
+pub fn sleep_ms(ms: u64) {
+    let deadline = ticks() + ms_to_ticks(ms);
+    while ticks() < deadline {
+        unsafe { asm!("hlt", options(nomem, nostack)) };
+    }
+}
+
+/// Call `callback` once, `ms` milliseconds from now.
+///
+/// The callback runs in the timer interrupt, so it must be short and must
+/// not take locks that code it interrupts might hold.
+#[allow(dead_code)]
+pub fn set_timer(ms: u64, callback: fn()) -> Result<TimerId, &'static str> {
+    let deadline = ticks() + ms_to_ticks(ms);
+
+    without_interrupts(|| {
+        let mut timers = TIMERS.lock();
+        let id = TimerId(timers.next_id);
+        let slot = timers
+            .pending
+            .iter_mut()
+            .find(|t| t.is_none())
+            .ok_or("too many pending timers")?;
+
+        *slot = Some(Timer {
+            id,
+            deadline,
+            callback,
+        });
+        timers.next_id = timers.next_id.wrapping_add(1);
+        Ok(id)
+    })
+}
+
+/// Drop a pending timer. Returns false if it already fired.
+#[allow(dead_code)]
+pub fn cancel_timer(id: TimerId) -> bool {
+    without_interrupts(|| {
+        let mut timers = TIMERS.lock();
+        match timers
+            .pending
+            .iter_mut()
+            .find(|t| t.is_some_and(|t| t.id == id))
+        {
+            Some(slot) => {
+                *slot = None;
+                true
+            }
+            None => false,
+        }
+    })
+}
+
+fn tick(_context: &mut InterruptContext) {
+    let now = {
+        let mut ticks = TICKS.lock();
+        *ticks += 1;
+        *ticks
+    };
+
+    // collect first so callbacks run without the lock, and may set timers
+    let mut expired = [None; MAX_TIMERS];
+    {
+        let mut timers = TIMERS.lock();
+        for (slot, out) in timers.pending.iter_mut().zip(expired.iter_mut()) {
+            if slot.is_some_and(|t| t.deadline <= now) {
+                *out = slot.take();
+            }
+        }
+    }
+
+    for timer in expired.iter().flatten() {
+        (timer.callback)();
+    }
+}
@@ -34,6 +34,10 @@ pub struct Port {
     parent_allocator: *mut PortAllocator,
 }
 
+// the allocator pointer is only used to release the port on drop, and the
+// allocator is required to outlive every port anyway
+unsafe impl Send for Port {}
+
 impl Port {
     pub fn inb(&mut self) -> u8 {
         let mut ret;
@@ -64,6 +68,7 @@ pub unsafe fn lockfree_inb(port_in: u16) -> u8 {
     ret
 }
 
+#[allow(dead_code)]
 pub unsafe fn lockfree_outb(port_out: u16, val: u8) {
     unsafe {
         asm!("out %al, %dx", in("dx") port_out, in("al") val, options(att_syntax));
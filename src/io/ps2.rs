@@ -0,0 +1,363 @@
+// Driver for the 8042 PS/2 controller.
+//
+// Port 0x64 takes controller commands and reads as the status register,
+// port 0x60 carries data both ways: replies from the controller and bytes
+// to and from the devices. Bytes for the second port are sent by prefixing
+// them with a controller command. The controller is slow, so every access
+// waits for the status register to say the buffer is ready, and gives up
+// after a while since there may be no controller at all.
+//
+// `init` runs the whole initialisation sequence, leaving each working port
+// enabled with its IRQ (1 and 12) on and its device reset. Translation to
+// scancode set 1 is kept on for the keyboard driver.
+//
+// See: https://wiki.osdev.org/I8042_PS/2_Controller
+
+use crate::interrupt::without_interrupts;
+use crate::io::ports::{Port, PortAllocator, lockfree_inb};
+use crate::utils::mutex::SpinMutex;
+use crate::{info, warn};
+
+use core::fmt;
+
+const DATA_PORT: u16 = 0x60;
+// status register on read
+const COMMAND_PORT: u16 = 0x64;
+
+const STATUS_OUTPUT_FULL: u8 = 1 << 0;
+const STATUS_INPUT_FULL: u8 = 1 << 1;
+const STATUS_SECOND_DATA: u8 = 1 << 5;
+
+const READ_CONFIG: u8 = 0x20;
+const WRITE_CONFIG: u8 = 0x60;
+const DISABLE_SECOND: u8 = 0xa7;
+const ENABLE_SECOND: u8 = 0xa8;
+const TEST_SECOND: u8 = 0xa9;
+const SELF_TEST: u8 = 0xaa;
+const TEST_FIRST: u8 = 0xab;
+const DISABLE_FIRST: u8 = 0xad;
+const ENABLE_FIRST: u8 = 0xae;
+const WRITE_SECOND: u8 = 0xd4;
+
+const SELF_TEST_PASSED: u8 = 0x55;
+const PORT_TEST_PASSED: u8 = 0x00;
+
+const CONFIG_FIRST_IRQ: u8 = 1 << 0;
+const CONFIG_SECOND_IRQ: u8 = 1 << 1;
+const CONFIG_FIRST_CLOCK_OFF: u8 = 1 << 4;
+const CONFIG_SECOND_CLOCK_OFF: u8 = 1 << 5;
+const CONFIG_TRANSLATE: u8 = 1 << 6;
+
+const DEVICE_RESET: u8 = 0xff;
+const ACK: u8 = 0xfa;
+const RESEND: u8 = 0xfe;
+const RESET_PASSED: u8 = 0xaa;
+const MAX_RESENDS: usize = 3;
+
+// status polls before giving up, a poll takes about a microsecond on real
+// hardware; a device reset runs its self-test and can take most of a second
+const POLLS: u32 = 100_000;
+const RESET_POLLS: u32 = 1_000_000;
+
+// more than the controller can buffer, in case a device keeps sending
+const MAX_FLUSH: usize = 64;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Channel {
+    First,
+    Second,
+}
+
+impl Channel {
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
+impl fmt::Display for Channel {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "port {}", self.index() + 1)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Ps2Error {
+    /// The I/O port is already owned by someone else.
+    PortInUse(u16),
+    /// The interrupt vector already has a handler.
+    VectorInUse(u8),
+    /// `init` hasn't run or failed.
+    NoController,
+    /// The controller didn't get ready in time.
+    Timeout,
+    /// The controller self-test replied with this instead of 0x55.
+    SelfTest(u8),
+    /// The controller doesn't have this port.
+    NoPort(Channel),
+    /// The interface test of a port failed with this code.
+    PortTest(Channel, u8),
+    /// A device answered a command with this instead of an ACK.
+    NoAck(u8),
+    /// A device reset replied with this instead of 0xaa.
+    ResetFailed(Channel, u8),
+}
+
+impl fmt::Display for Ps2Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Ps2Error::PortInUse(port) => write!(f, "I/O port {:#x} in use", port),
+            Ps2Error::VectorInUse(vector) => write!(f, "interrupt vector {:#x} in use", vector),
+            Ps2Error::NoController => write!(f, "controller not initialised"),
+            Ps2Error::Timeout => write!(f, "timed out"),
+            Ps2Error::SelfTest(reply) => write!(f, "controller self-test failed ({:#x})", reply),
+            Ps2Error::NoPort(channel) => write!(f, "no {}", channel),
+            Ps2Error::PortTest(channel, code) => {
+                let reason = match code {
+                    0x01 => "clock line stuck low",
+                    0x02 => "clock line stuck high",
+                    0x03 => "data line stuck low",
+                    0x04 => "data line stuck high",
+                    _ => "unknown failure",
+                };
+                write!(f, "{} test failed, {} ({:#x})", channel, reason, code)
+            }
+            Ps2Error::NoAck(reply) => write!(f, "device replied {:#x} instead of ACK", reply),
+            Ps2Error::ResetFailed(channel, reply) => {
+                write!(f, "{} device reset failed ({:#x})", channel, reply)
+            }
+        }
+    }
+}
+
+struct Controller {
+    command: Port,
+    data: Port,
+    ports: [Result<(), Ps2Error>; 2],
+}
+
+impl Controller {
+    fn status(&mut self) -> u8 {
+        self.command.inb()
+    }
+
+    fn wait_until(&mut self, polls: u32, ready: impl Fn(u8) -> bool) -> Result<(), Ps2Error> {
+        for _ in 0..polls {
+            if ready(self.status()) {
+                return Ok(());
+            }
+        }
+        Err(Ps2Error::Timeout)
+    }
+
+    fn read_polls(&mut self, polls: u32) -> Result<u8, Ps2Error> {
+        self.wait_until(polls, |s| s & STATUS_OUTPUT_FULL != 0)?;
+        Ok(self.data.inb())
+    }
+
+    fn read(&mut self) -> Result<u8, Ps2Error> {
+        self.read_polls(POLLS)
+    }
+
+    fn write(&mut self, byte: u8) -> Result<(), Ps2Error> {
+        self.wait_until(POLLS, |s| s & STATUS_INPUT_FULL == 0)?;
+        self.data.outb(byte);
+        Ok(())
+    }
+
+    fn send(&mut self, command: u8) -> Result<(), Ps2Error> {
+        self.wait_until(POLLS, |s| s & STATUS_INPUT_FULL == 0)?;
+        self.command.outb(command);
+        Ok(())
+    }
+
+    fn query(&mut self, command: u8) -> Result<u8, Ps2Error> {
+        self.send(command)?;
+        self.read()
+    }
+
+    /// Throw away whatever is waiting in the output buffer.
+    fn flush(&mut self) {
+        for _ in 0..MAX_FLUSH {
+            if self.status() & STATUS_OUTPUT_FULL == 0 {
+                break;
+            }
+            self.data.inb();
+        }
+    }
+
+    fn read_config(&mut self) -> Result<u8, Ps2Error> {
+        self.query(READ_CONFIG)
+    }
+
+    fn write_config(&mut self, config: u8) -> Result<(), Ps2Error> {
+        self.send(WRITE_CONFIG)?;
+        self.write(config)
+    }
+
+    fn write_device(&mut self, channel: Channel, byte: u8) -> Result<(), Ps2Error> {
+        self.ports[channel.index()]?;
+        if channel == Channel::Second {
+            self.send(WRITE_SECOND)?;
+        }
+        self.write(byte)
+    }
+
+    /// Send `byte` to a device and wait for its ACK, resending if asked to.
+    fn device_command(&mut self, channel: Channel, byte: u8) -> Result<(), Ps2Error> {
+        for _ in 0..MAX_RESENDS {
+            self.write_device(channel, byte)?;
+            match self.read()? {
+                ACK => return Ok(()),
+                RESEND => continue,
+                reply => return Err(Ps2Error::NoAck(reply)),
+            }
+        }
+        Err(Ps2Error::NoAck(RESEND))
+    }
+
+    fn reset_device(&mut self, channel: Channel) -> Result<(), Ps2Error> {
+        self.device_command(channel, DEVICE_RESET)?;
+        match self.read_polls(RESET_POLLS)? {
+            RESET_PASSED => Ok(()),
+            reply => Err(Ps2Error::ResetFailed(channel, reply)),
+        }
+    }
+
+    fn test_port(&mut self, channel: Channel) -> Result<(), Ps2Error> {
+        let test = match channel {
+            Channel::First => TEST_FIRST,
+            Channel::Second => TEST_SECOND,
+        };
+        match self.query(test)? {
+            PORT_TEST_PASSED => Ok(()),
+            code => Err(Ps2Error::PortTest(channel, code)),
+        }
+    }
+
+    fn init(&mut self) -> Result<(), Ps2Error> {
+        // keep the devices from sending anything while this runs
+        self.send(DISABLE_FIRST)?;
+        self.send(DISABLE_SECOND)?;
+        self.flush();
+
+        let mut config = self.read_config()?;
+        // a second port's clock would have been stopped by disabling it
+        let maybe_dual = config & CONFIG_SECOND_CLOCK_OFF != 0;
+        config &= !(CONFIG_FIRST_IRQ | CONFIG_SECOND_IRQ | CONFIG_FIRST_CLOCK_OFF);
+        config |= CONFIG_TRANSLATE;
+        self.write_config(config)?;
+
+        match self.query(SELF_TEST)? {
+            SELF_TEST_PASSED => {}
+            reply => return Err(Ps2Error::SelfTest(reply)),
+        }
+        // the self-test may have reset the controller
+        self.write_config(config)?;
+
+        let mut dual = false;
+        if maybe_dual {
+            self.send(ENABLE_SECOND)?;
+            dual = self.read_config()? & CONFIG_SECOND_CLOCK_OFF == 0;
+            self.send(DISABLE_SECOND)?;
+        }
+
+        self.ports[0] = self.test_port(Channel::First);
+        self.ports[1] = if dual {
+            self.test_port(Channel::Second)
+        } else {
+            Err(Ps2Error::NoPort(Channel::Second))
+        };
+        if self.ports.iter().all(Result::is_err) {
+            return self.ports[0];
+        }
+
+        if self.ports[0].is_ok() {
+            self.send(ENABLE_FIRST)?;
+            self.ports[0] = self.reset_device(Channel::First);
+        }
+        if self.ports[1].is_ok() {
+            self.send(ENABLE_SECOND)?;
+            self.ports[1] = self.reset_device(Channel::Second);
+        }
+        // mice follow the reset reply with their device ID
+        self.flush();
+
+        let mut config = self.read_config()?;
+        if self.ports[0].is_ok() {
+            config |= CONFIG_FIRST_IRQ;
+        }
+        if self.ports[1].is_ok() {
+            config |= CONFIG_SECOND_IRQ;
+        }
+        self.write_config(config)
+    }
+}
+
+static PS2: SpinMutex<Option<Controller>> = SpinMutex::new(None);
+
+// device replies arrive through the data port the IRQ handlers read too
+fn with_controller<R>(
+    f: impl FnOnce(&mut Controller) -> Result<R, Ps2Error>,
+) -> Result<R, Ps2Error> {
+    without_interrupts(|| match PS2.lock().as_mut() {
+        Some(controller) => f(controller),
+        None => Err(Ps2Error::NoController),
+    })
+}
+
+pub fn init(palloc: &mut PortAllocator) -> Result<(), Ps2Error> {
+    let command = palloc
+        .allocate(COMMAND_PORT)
+        .ok_or(Ps2Error::PortInUse(COMMAND_PORT))?;
+    let data = palloc
+        .allocate(DATA_PORT)
+        .ok_or(Ps2Error::PortInUse(DATA_PORT))?;
+
+    let mut controller = Controller {
+        command,
+        data,
+        ports: [Err(Ps2Error::NoController); 2],
+    };
+    without_interrupts(|| controller.init())?;
+
+    for channel in [Channel::First, Channel::Second] {
+        match controller.ports[channel.index()] {
+            Ok(()) => info!("{} ready", channel),
+            Err(e) => warn!("{}", e),
+        }
+    }
+
+    *PS2.lock() = Some(controller);
+    Ok(())
+}
+
+/// Read the byte the IRQ of `channel` was raised for.
+///
+/// For interrupt handlers, which can't take the controller lock. Returns
+/// `None` if the byte is gone already, e.g. read by `device_command` while
+/// interrupts were off, or came from the other device.
+pub fn irq_byte(channel: Channel) -> Option<u8> {
+    let status = unsafe { lockfree_inb(COMMAND_PORT) };
+    let from_second = status & STATUS_SECOND_DATA != 0;
+    if status & STATUS_OUTPUT_FULL == 0 || from_second != (channel == Channel::Second) {
+        return None;
+    }
+    Some(unsafe { lockfree_inb(DATA_PORT) })
+}
+
+/// Whether `channel` passed its tests and has a device that reset fine.
+pub fn is_working(channel: Channel) -> bool {
+    with_controller(|c| c.ports[channel.index()]).is_ok()
+}
+
+/// Send a command byte to the device on `channel` and wait for its ACK.
+#[allow(dead_code)]
+pub fn device_command(channel: Channel, byte: u8) -> Result<(), Ps2Error> {
+    with_controller(|c| c.device_command(channel, byte))
+}
+
+/// Wait for the next byte from either device.
+#[allow(dead_code)]
+pub fn read_data() -> Result<u8, Ps2Error> {
+    with_controller(Controller::read)
+}
@@ -0,0 +1,266 @@
+// Driver for the CMOS real-time clock.
+//
+// CMOS registers are read by writing their index to port 0x70 and reading
+// port 0x71. Bit 7 of the index disables NMIs, which is kept set while a
+// read is in progress so an NMI handler can't leave the index pointing
+// somewhere else.
+//
+// The clock keeps counting while it's read, so a read can see a half
+// updated time. The time is read twice, after waiting out any update in
+// progress, until both reads agree. Values can be BCD or binary and hours
+// can be 12 or 24-hour, depending on status register B.
+//
+// See: https://wiki.osdev.org/CMOS
+
+use crate::interrupt::{self, InterruptContext, pic, without_interrupts};
+use crate::io::ports::{Port, PortAllocator};
+use crate::utils::mutex::SpinMutex;
+
+use core::fmt;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+const IRQ: u8 = 8;
+
+const NMI_DISABLE: u8 = 0x80;
+
+const SECONDS: u8 = 0x00;
+const MINUTES: u8 = 0x02;
+const HOURS: u8 = 0x04;
+const DAY: u8 = 0x07;
+const MONTH: u8 = 0x08;
+const YEAR: u8 = 0x09;
+// not standardised, the ACPI FADT names the real one, but this is where
+// every PC chipset and QEMU keep it
+const CENTURY: u8 = 0x32;
+const STATUS_A: u8 = 0x0a;
+const STATUS_B: u8 = 0x0b;
+const STATUS_C: u8 = 0x0c;
+const STATUS_D: u8 = 0x0d;
+
+const A_UPDATE_IN_PROGRESS: u8 = 1 << 7;
+const B_PERIODIC_INTERRUPT: u8 = 1 << 6;
+const B_UPDATE_INTERRUPT: u8 = 1 << 4;
+const B_BINARY: u8 = 1 << 2;
+const B_24_HOUR: u8 = 1 << 1;
+const C_PERIODIC: u8 = 1 << 6;
+const C_UPDATE: u8 = 1 << 4;
+const HOUR_PM: u8 = 1 << 7;
+
+/// Wall-clock time as kept by the RTC, usually UTC.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct DateTime {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+impl fmt::Display for DateTime {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+            self.year, self.month, self.day, self.hour, self.minute, self.second
+        )
+    }
+}
+
+/// What the RTC raises IRQ 8 for.
+#[allow(dead_code)]
+#[derive(Clone, Copy)]
+pub enum InterruptMode {
+    /// Once a second, after the time is updated. `now` then returns the time
+    /// cached by the handler instead of reading the CMOS.
+    Update,
+    /// At 32768 >> (rate - 1) Hz, rate being 3 (8 kHz) to 15 (2 Hz).
+    Periodic { rate: u8 },
+}
+
+// raw register values, before BCD and 12-hour conversion
+#[derive(Clone, Copy, PartialEq, Eq)]
+struct Registers {
+    second: u8,
+    minute: u8,
+    hour: u8,
+    day: u8,
+    month: u8,
+    year: u8,
+    century: u8,
+}
+
+struct Rtc {
+    index: Port,
+    data: Port,
+}
+
+impl Rtc {
+    fn read(&mut self, reg: u8) -> u8 {
+        self.index.outb(NMI_DISABLE | reg);
+        self.data.inb()
+    }
+
+    fn write(&mut self, reg: u8, value: u8) {
+        self.index.outb(NMI_DISABLE | reg);
+        self.data.outb(value);
+    }
+
+    /// Point the index at a harmless register with NMIs enabled again.
+    fn release_nmi(&mut self) {
+        self.index.outb(STATUS_D);
+        self.data.inb();
+    }
+
+    fn read_registers(&mut self) -> Registers {
+        while self.read(STATUS_A) & A_UPDATE_IN_PROGRESS != 0 {}
+
+        Registers {
+            second: self.read(SECONDS),
+            minute: self.read(MINUTES),
+            hour: self.read(HOURS),
+            day: self.read(DAY),
+            month: self.read(MONTH),
+            year: self.read(YEAR),
+            century: self.read(CENTURY),
+        }
+    }
+
+    fn now(&mut self) -> DateTime {
+        let mut regs = self.read_registers();
+        loop {
+            let again = self.read_registers();
+            if again == regs {
+                break;
+            }
+            regs = again;
+        }
+
+        let status_b = self.read(STATUS_B);
+        self.release_nmi();
+        decode(regs, status_b)
+    }
+}
+
+fn from_bcd(value: u8) -> u8 {
+    (value >> 4) * 10 + (value & 0x0f)
+}
+
+fn decode(regs: Registers, status_b: u8) -> DateTime {
+    let binary = status_b & B_BINARY != 0;
+    let convert = |v: u8| if binary { v } else { from_bcd(v) };
+
+    let pm = regs.hour & HOUR_PM != 0;
+    let mut hour = convert(regs.hour & !HOUR_PM);
+    if status_b & B_24_HOUR == 0 {
+        // 12 AM is midnight, 12 PM is noon
+        hour = match (hour, pm) {
+            (12, false) => 0,
+            (12, true) => 12,
+            (h, true) => h + 12,
+            (h, false) => h,
+        };
+    }
+
+    let century = match convert(regs.century) {
+        c @ 19..=21 => u16::from(c),
+        _ => 20, // no century register after all
+    };
+
+    DateTime {
+        year: century * 100 + u16::from(convert(regs.year)),
+        month: convert(regs.month),
+        day: convert(regs.day),
+        hour,
+        minute: convert(regs.minute),
+        second: convert(regs.second),
+    }
+}
+
+static RTC: SpinMutex<Option<Rtc>> = SpinMutex::new(None);
+
+// kept up to date by the update interrupt, if enabled
+static CACHED: SpinMutex<Option<DateTime>> = SpinMutex::new(None);
+
+static PERIODIC_TICKS: AtomicUsize = AtomicUsize::new(0);
+
+// the IRQ handler uses the ports too
+fn with_rtc<R>(f: impl FnOnce(&mut Rtc) -> R) -> R {
+    without_interrupts(|| f(RTC.lock().as_mut().expect("RTC is initialised")))
+}
+
+pub fn init(palloc: &mut PortAllocator) -> Result<(), &'static str> {
+    let index = palloc.allocate(0x70).ok_or("CMOS index port in use")?;
+    let data = palloc.allocate(0x71).ok_or("CMOS data port in use")?;
+    *RTC.lock() = Some(Rtc { index, data });
+    Ok(())
+}
+
+/// Current wall-clock time.
+pub fn now() -> DateTime {
+    if let Some(time) = without_interrupts(|| *CACHED.lock()) {
+        return time;
+    }
+    with_rtc(Rtc::now)
+}
+
+/// Number of periodic interrupts so far.
+#[allow(dead_code)]
+pub fn periodic_ticks() -> usize {
+    PERIODIC_TICKS.load(Ordering::Relaxed)
+}
+
+/// Have the RTC raise IRQ 8.
+#[allow(dead_code)]
+pub fn enable_interrupt(mode: InterruptMode) -> Result<(), &'static str> {
+    if let InterruptMode::Periodic { rate } = mode {
+        if !(3..=15).contains(&rate) {
+            return Err("RTC periodic rate out of range");
+        }
+    }
+
+    interrupt::register_handler(pic::irq_vector(IRQ), rtc_interrupt)?;
+
+    with_rtc(|rtc| {
+        let enable = match mode {
+            InterruptMode::Update => {
+                let now = rtc.now();
+                *CACHED.lock() = Some(now);
+                B_UPDATE_INTERRUPT
+            }
+            InterruptMode::Periodic { rate } => {
+                let a = rtc.read(STATUS_A);
+                rtc.write(STATUS_A, (a & 0xf0) | rate);
+                B_PERIODIC_INTERRUPT
+            }
+        };
+
+        let b = rtc.read(STATUS_B);
+        rtc.write(STATUS_B, b | enable);
+        // anything already pending would keep IRQ 8 from firing
+        rtc.read(STATUS_C);
+        rtc.release_nmi();
+    });
+
+    interrupt::unmask_irq(IRQ);
+    Ok(())
+}
+
+fn rtc_interrupt(_context: &mut InterruptContext) {
+    let mut rtc = RTC.lock();
+    let rtc = rtc.as_mut().expect("RTC is initialised");
+
+    // reading C acknowledges the interrupt, the RTC won't raise another
+    // one until it has been read
+    let cause = rtc.read(STATUS_C);
+    if cause & C_PERIODIC != 0 {
+        PERIODIC_TICKS.fetch_add(1, Ordering::Relaxed);
+    }
+    if cause & C_UPDATE != 0 {
+        // the update just finished, there is a whole second to read it
+        let now = rtc.now();
+        *CACHED.lock() = Some(now);
+    } else {
+        rtc.release_nmi();
+    }
+}
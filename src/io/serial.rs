@@ -0,0 +1,275 @@
+// Driver for 16550 UARTs on the standard PC serial ports.
+//
+// Each UART has eight registers at consecutive ports from its base. The
+// first two double as the baud rate divisor while the DLAB bit of the line
+// control register is set. A UART is only used if its scratch register
+// holds a value and it echoes a byte back in loopback mode, since reading
+// a missing one just returns 0xff.
+//
+// Both directions are buffered. Received bytes are moved into a queue by
+// the interrupt handler. Written bytes are queued and moved into the
+// 16 byte transmit FIFO whenever it empties, which the UART signals with
+// an interrupt. If the queue fills up, e.g. with interrupts off, the writer
+// waits for the FIFO itself.
+//
+// See: https://wiki.osdev.org/Serial_Ports
+
+use crate::interrupt::{self, InterruptContext, pic, without_interrupts};
+use crate::io::ports::{Port, PortAllocator};
+use crate::utils::mutex::SpinMutex;
+use crate::utils::spsc::SpscQueue;
+
+use core::fmt;
+
+// register offsets from the base port
+const DATA: u16 = 0; // divisor low byte with DLAB set
+const INTERRUPT_ENABLE: u16 = 1; // divisor high byte with DLAB set
+const FIFO_CONTROL: u16 = 2; // interrupt identification on read
+const LINE_CONTROL: u16 = 3;
+const MODEM_CONTROL: u16 = 4;
+const LINE_STATUS: u16 = 5;
+const SCRATCH: u16 = 7;
+const REGISTERS: u16 = 8;
+
+const IER_RECEIVED: u8 = 1 << 0;
+const IER_TRANSMIT_EMPTY: u8 = 1 << 1;
+
+// enable, clear both FIFOs, receive interrupt at 14 bytes
+const FCR_ENABLE: u8 = 0xc7;
+
+const LCR_8N1: u8 = 0x03;
+const LCR_DLAB: u8 = 1 << 7;
+
+const MCR_DTR: u8 = 1 << 0;
+const MCR_RTS: u8 = 1 << 1;
+const MCR_OUT1: u8 = 1 << 2;
+// gates the UART's IRQ line on PCs
+const MCR_OUT2: u8 = 1 << 3;
+const MCR_LOOPBACK: u8 = 1 << 4;
+
+const LSR_DATA_READY: u8 = 1 << 0;
+const LSR_TRANSMIT_EMPTY: u8 = 1 << 5;
+
+const FIFO_SIZE: usize = 16;
+const BASE_BAUD: u32 = 115_200;
+const TEST_BYTE: u8 = 0xae;
+
+const RX_SIZE: usize = 256;
+const TX_SIZE: usize = 1024;
+
+#[allow(dead_code)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ComPort {
+    Com1,
+    Com2,
+    Com3,
+    Com4,
+}
+
+impl ComPort {
+    const ALL: [ComPort; 4] = [ComPort::Com1, ComPort::Com2, ComPort::Com3, ComPort::Com4];
+
+    fn index(self) -> usize {
+        self as usize
+    }
+
+    fn base(self) -> u16 {
+        [0x3f8, 0x2f8, 0x3e8, 0x2e8][self.index()]
+    }
+
+    // COM1 and COM3, COM2 and COM4 share a line
+    fn irq(self) -> u8 {
+        [4, 3, 4, 3][self.index()]
+    }
+}
+
+struct Uart {
+    registers: [Port; REGISTERS as usize],
+    rx: SpscQueue<u8, RX_SIZE>,
+    tx: SpscQueue<u8, TX_SIZE>,
+}
+
+impl Uart {
+    fn read(&mut self, reg: u16) -> u8 {
+        self.registers[reg as usize].inb()
+    }
+
+    fn write(&mut self, reg: u16, value: u8) {
+        self.registers[reg as usize].outb(value);
+    }
+
+    fn is_present(&mut self) -> bool {
+        self.write(SCRATCH, TEST_BYTE);
+        if self.read(SCRATCH) != TEST_BYTE {
+            return false;
+        }
+
+        self.write(MODEM_CONTROL, MCR_LOOPBACK | MCR_RTS | MCR_OUT1 | MCR_OUT2);
+        self.write(DATA, TEST_BYTE);
+        self.read(DATA) == TEST_BYTE
+    }
+
+    fn configure(&mut self, divisor: u16) {
+        self.write(INTERRUPT_ENABLE, 0);
+
+        let [low, high] = divisor.to_le_bytes();
+        self.write(LINE_CONTROL, LCR_DLAB);
+        self.write(DATA, low);
+        self.write(INTERRUPT_ENABLE, high);
+        self.write(LINE_CONTROL, LCR_8N1);
+
+        self.write(FIFO_CONTROL, FCR_ENABLE);
+        self.write(MODEM_CONTROL, MCR_DTR | MCR_RTS | MCR_OUT1 | MCR_OUT2);
+    }
+
+    /// Move queued bytes into the transmit FIFO, if it's empty.
+    fn transmit(&mut self) {
+        if self.read(LINE_STATUS) & LSR_TRANSMIT_EMPTY == 0 {
+            return;
+        }
+
+        for _ in 0..FIFO_SIZE {
+            match self.tx.pop() {
+                Some(byte) => self.write(DATA, byte),
+                None => break,
+            }
+        }
+
+        // the UART interrupts once the FIFO is empty again, only wanted
+        // while there is more to send
+        let ier = if self.tx.is_empty() {
+            IER_RECEIVED
+        } else {
+            IER_RECEIVED | IER_TRANSMIT_EMPTY
+        };
+        self.write(INTERRUPT_ENABLE, ier);
+    }
+
+    fn receive(&mut self) {
+        while self.read(LINE_STATUS) & LSR_DATA_READY != 0 {
+            let byte = self.read(DATA);
+            // nobody is reading, drop the newest bytes
+            let _ = self.rx.push(byte);
+        }
+    }
+
+    fn write_byte(&mut self, byte: u8) {
+        while self.tx.push(byte).is_err() {
+            self.transmit();
+        }
+        self.transmit();
+    }
+}
+
+static UARTS: [SpinMutex<Option<Uart>>; 4] = [
+    SpinMutex::new(None),
+    SpinMutex::new(None),
+    SpinMutex::new(None),
+    SpinMutex::new(None),
+];
+
+// the IRQ handler uses the ports and queues too
+fn with_uart<R>(com: ComPort, f: impl FnOnce(&mut Uart) -> R) -> Option<R> {
+    without_interrupts(|| UARTS[com.index()].lock().as_mut().map(f))
+}
+
+/// Find and set up the UART for `com`, sending and receiving at `baud`
+/// with 8 data bits, no parity and one stop bit.
+pub fn init(palloc: &mut PortAllocator, com: ComPort, baud: u32) -> Result<(), &'static str> {
+    if baud == 0 || BASE_BAUD % baud != 0 {
+        return Err("baud rate must divide 115200");
+    }
+
+    let base = com.base();
+    let mut ports = [const { None }; REGISTERS as usize];
+    for (offset, port) in (0..REGISTERS).zip(&mut ports) {
+        *port = Some(palloc.allocate(base + offset).ok_or("UART port in use")?);
+    }
+    let mut uart = Uart {
+        registers: ports.map(|p| p.expect("all ports allocated")),
+        rx: SpscQueue::new(),
+        tx: SpscQueue::new(),
+    };
+
+    if !uart.is_present() {
+        return Err("no UART");
+    }
+    uart.configure((BASE_BAUD / baud) as u16);
+    uart.write(INTERRUPT_ENABLE, IER_RECEIVED);
+
+    without_interrupts(|| *UARTS[com.index()].lock() = Some(uart));
+
+    // the other port on the line may have registered it already
+    let _ = interrupt::register_handler(pic::irq_vector(com.irq()), serial_interrupt);
+    interrupt::unmask_irq(com.irq());
+    Ok(())
+}
+
+/// Whether `init` found a UART for `com`.
+#[allow(dead_code)]
+pub fn is_present(com: ComPort) -> bool {
+    with_uart(com, |_| ()).is_some()
+}
+
+/// Queue `bytes` for sending. Does nothing if `com` has no UART.
+pub fn write_bytes(com: ComPort, bytes: &[u8]) {
+    with_uart(com, |uart| {
+        for &byte in bytes {
+            uart.write_byte(byte);
+        }
+    });
+}
+
+/// Next received byte, if any.
+#[allow(dead_code)]
+pub fn read_byte(com: ComPort) -> Option<u8> {
+    with_uart(com, |uart| uart.rx.pop()).flatten()
+}
+
+/// Fill `buf` with received bytes, returns how many there were.
+#[allow(dead_code)]
+pub fn read(com: ComPort, buf: &mut [u8]) -> usize {
+    with_uart(com, |uart| {
+        let mut n = 0;
+        while n < buf.len() {
+            match uart.rx.pop() {
+                Some(byte) => buf[n] = byte,
+                None => break,
+            }
+            n += 1;
+        }
+        n
+    })
+    .unwrap_or(0)
+}
+
+/// `fmt::Write` for a serial port, turning `\n` into `\r\n` for terminals.
+pub struct SerialWriter(pub ComPort);
+
+impl fmt::Write for SerialWriter {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for (i, line) in s.split('\n').enumerate() {
+            if i > 0 {
+                write_bytes(self.0, b"\r\n");
+            }
+            write_bytes(self.0, line.as_bytes());
+        }
+        Ok(())
+    }
+}
+
+/// Release the lock on `com` for a fatal error handler, see
+/// `SpinMutex::force_unlock`.
+pub unsafe fn force_unlock(com: ComPort) {
+    unsafe { UARTS[com.index()].force_unlock() };
+}
+
+fn serial_interrupt(context: &mut InterruptContext) {
+    let irq = pic::vector_irq(context.vector as u8);
+    for com in ComPort::ALL.into_iter().filter(|c| Some(c.irq()) == irq) {
+        if let Some(uart) = UARTS[com.index()].lock().as_mut() {
+            uart.receive();
+            uart.transmit();
+        }
+    }
+}
@@ -1,8 +1,11 @@
+use crate::interrupt::without_interrupts;
+use crate::io::serial::{ComPort, SerialWriter};
+use crate::paging::phys_to_virt;
 use crate::utils::mutex::SpinMutex;
 
 const BUFFER_WIDTH: usize = 80;
 const BUFFER_HEIGHT: usize = 25;
-const BUFFER_LOCATION: *mut u16 = 0xb8000 as *mut u16;
+const BUFFER_LOCATION: *mut u16 = phys_to_virt(0xb8000) as *mut u16;
 
 #[allow(dead_code)]
 pub enum Colours {
@@ -136,7 +139,11 @@ pub fn init_writer() {
 #[doc(hidden)]
 pub fn print_(args: core::fmt::Arguments) {
     use core::fmt::Write;
-    WRITER.lock().write_fmt(args).unwrap();
+    // interrupt handlers print and log to the screen too
+    without_interrupts(|| WRITER.lock().write_fmt(args)).unwrap();
+    crate::dmesg::write_fmt(args);
+    // for when nobody is looking at the screen, e.g. QEMU with -serial stdio
+    let _ = SerialWriter(ComPort::Com1).write_fmt(args);
 }
 
 #[macro_export]
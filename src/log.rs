@@ -0,0 +1,293 @@
+// Leveled logging.
+//
+// `error!` to `trace!` format a message and hand it, tagged with its level,
+// the module it came from and the time since boot, to every registered
+// sink. A message is dropped unless its level passes both the filter of
+// the module it came from and the level of the sink.
+//
+// The kernel command line sets the filters:
+//
+//   loglevel=debug                 default for every module
+//   log=io::ps2:trace,paging:warn  per module, the longest prefix wins
+//   log.serial=trace               per sink
+//
+// Module paths are written without the crate name. The default is `info`.
+
+use crate::cmdline;
+use crate::dmesg;
+use crate::interrupt::without_interrupts;
+use crate::io::serial::{ComPort, SerialWriter};
+use crate::io::vga;
+use crate::println;
+use crate::time;
+use crate::utils::mutex::SpinMutex;
+
+use core::fmt::{self, Write};
+use core::sync::atomic::{AtomicU8, Ordering};
+
+const MAX_SINKS: usize = 4;
+const MAX_FILTERS: usize = 16;
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum Level {
+    Error = 1,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl Level {
+    const ALL: [Level; 5] = [
+        Level::Error,
+        Level::Warn,
+        Level::Info,
+        Level::Debug,
+        Level::Trace,
+    ];
+
+    fn name(self) -> &'static str {
+        match self {
+            Level::Error => "error",
+            Level::Warn => "warn",
+            Level::Info => "info",
+            Level::Debug => "debug",
+            Level::Trace => "trace",
+        }
+    }
+
+    fn parse(name: &str) -> Option<Level> {
+        Self::ALL.into_iter().find(|l| l.name() == name)
+    }
+
+    fn from_u8(value: u8) -> Level {
+        Self::ALL[usize::from(value) - 1]
+    }
+}
+
+/// One message on its way to the sinks.
+pub struct Record<'a> {
+    pub level: Level,
+    /// Module path without the crate name.
+    pub module: &'a str,
+    pub nanos: u64,
+    pub args: fmt::Arguments<'a>,
+}
+
+impl fmt::Display for Record<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "[{:5}.{:06}] {:5} {}: {}",
+            self.nanos / 1_000_000_000,
+            self.nanos % 1_000_000_000 / 1000,
+            self.level.name(),
+            self.module,
+            self.args
+        )
+    }
+}
+
+pub trait Sink: Sync {
+    /// What `log.<name>=` on the command line refers to.
+    fn name(&self) -> &'static str;
+
+    fn write(&self, record: &Record);
+}
+
+/// The screen, through the same writer as `print!`.
+pub struct VgaSink;
+
+impl Sink for VgaSink {
+    fn name(&self) -> &'static str {
+        "vga"
+    }
+
+    fn write(&self, record: &Record) {
+        let _ = writeln!(vga::WRITER.lock(), "{}", record);
+    }
+}
+
+/// COM1, if there is a UART.
+pub struct SerialSink;
+
+impl Sink for SerialSink {
+    fn name(&self) -> &'static str {
+        "serial"
+    }
+
+    fn write(&self, record: &Record) {
+        let _ = writeln!(SerialWriter(ComPort::Com1), "{}", record);
+    }
+}
+
+/// The kernel message buffer, see `dmesg`.
+pub struct DmesgSink;
+
+impl Sink for DmesgSink {
+    fn name(&self) -> &'static str {
+        "dmesg"
+    }
+
+    fn write(&self, record: &Record) {
+        dmesg::write_fmt(format_args!("{}\n", record));
+    }
+}
+
+struct SinkEntry {
+    sink: &'static dyn Sink,
+    level: Level,
+}
+
+struct Filter {
+    prefix: &'static str,
+    level: Level,
+}
+
+static SINKS: SpinMutex<[Option<SinkEntry>; MAX_SINKS]> =
+    SpinMutex::new([const { None }; MAX_SINKS]);
+static FILTERS: SpinMutex<[Option<Filter>; MAX_FILTERS]> =
+    SpinMutex::new([const { None }; MAX_FILTERS]);
+static DEFAULT_LEVEL: AtomicU8 = AtomicU8::new(Level::Info as u8);
+
+pub static VGA_SINK: VgaSink = VgaSink;
+pub static SERIAL_SINK: SerialSink = SerialSink;
+pub static DMESG_SINK: DmesgSink = DmesgSink;
+
+fn module_level(module: &str) -> Level {
+    let filters = FILTERS.lock();
+    filters
+        .iter()
+        .flatten()
+        .filter(|f| {
+            // whole path segments only, `io` shouldn't match `iommu`
+            module
+                .strip_prefix(f.prefix)
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with("::"))
+        })
+        .max_by_key(|f| f.prefix.len())
+        .map_or_else(
+            || Level::from_u8(DEFAULT_LEVEL.load(Ordering::Relaxed)),
+            |f| f.level,
+        )
+}
+
+fn add_filter(prefix: &'static str, level: Level) -> Result<(), &'static str> {
+    let mut filters = FILTERS.lock();
+    let slot = filters
+        .iter_mut()
+        .find(|f| f.is_none())
+        .ok_or("too many module filters")?;
+    *slot = Some(Filter { prefix, level });
+    Ok(())
+}
+
+fn parse_level(value: Option<&str>) -> Result<Level, &'static str> {
+    value.and_then(Level::parse).ok_or("unknown log level")
+}
+
+fn apply_option(key: &str, value: Option<&'static str>) -> Result<(), &'static str> {
+    if key == "loglevel" {
+        DEFAULT_LEVEL.store(parse_level(value)? as u8, Ordering::Relaxed);
+    } else if key == "log" {
+        for filter in value.unwrap_or("").split(',') {
+            let (prefix, level) = filter.rsplit_once(':').ok_or("expected module:level")?;
+            add_filter(prefix, parse_level(Some(level))?)?;
+        }
+    }
+    Ok(())
+}
+
+/// Add `sink`, taking messages up to `level` unless the command line says
+/// otherwise. A bad level on the command line is reported and ignored, the
+/// only error is running out of sink slots.
+pub fn register_sink(sink: &'static dyn Sink, level: Level) -> Result<(), &'static str> {
+    let key = |k: &str| k.strip_prefix("log.") == Some(sink.name());
+    let level = match cmdline::options().find(|&(k, _)| key(k)) {
+        Some((key, value)) => parse_level(value).unwrap_or_else(|e| {
+            println!("log: {} in `{}`", e, key);
+            level
+        }),
+        None => level,
+    };
+
+    without_interrupts(|| {
+        let mut sinks = SINKS.lock();
+        let slot = sinks
+            .iter_mut()
+            .find(|s| s.is_none())
+            .ok_or("too many log sinks")?;
+        *slot = Some(SinkEntry { sink, level });
+        Ok(())
+    })
+}
+
+/// Read the filters from the command line and start logging to the screen
+/// and the kernel message buffer. Needs `cmdline::init`.
+pub fn init() {
+    for (key, value) in cmdline::options() {
+        if let Err(e) = apply_option(key, value) {
+            println!("log: {} in `{}`", e, key);
+        }
+    }
+
+    register_sink(&VGA_SINK, Level::Info).expect("sink slot is free");
+    register_sink(&DMESG_SINK, Level::Trace).expect("sink slot is free");
+}
+
+#[doc(hidden)]
+pub fn log_(level: Level, module: &'static str, args: fmt::Arguments) {
+    let module = module.split_once("::").map_or(module, |(_, rest)| rest);
+
+    // handlers log too, and the sinks take locks that must not be held
+    // when one of them runs
+    without_interrupts(|| {
+        if level > module_level(module) {
+            return;
+        }
+
+        let record = Record {
+            level,
+            module,
+            nanos: time::nanos_since_boot(),
+            args,
+        };
+        for entry in SINKS.lock().iter().flatten() {
+            if level <= entry.level {
+                entry.sink.write(&record);
+            }
+        }
+    });
+}
+
+#[macro_export]
+macro_rules! log {
+    ($level:expr, $($arg:tt)*) => {
+        $crate::log::log_($level, module_path!(), format_args!($($arg)*))
+    };
+}
+
+#[macro_export]
+macro_rules! error {
+    ($($arg:tt)*) => ($crate::log!($crate::log::Level::Error, $($arg)*));
+}
+
+#[macro_export]
+macro_rules! warn {
+    ($($arg:tt)*) => ($crate::log!($crate::log::Level::Warn, $($arg)*));
+}
+
+#[macro_export]
+macro_rules! info {
+    ($($arg:tt)*) => ($crate::log!($crate::log::Level::Info, $($arg)*));
+}
+
+#[macro_export]
+macro_rules! debug {
+    ($($arg:tt)*) => ($crate::log!($crate::log::Level::Debug, $($arg)*));
+}
+
+#[macro_export]
+macro_rules! trace {
+    ($($arg:tt)*) => ($crate::log!($crate::log::Level::Trace, $($arg)*));
+}
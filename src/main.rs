@@ -2,63 +2,138 @@
 #![no_std]
 #![no_main]
 #![feature(abi_x86_interrupt)]
+#![feature(custom_test_frameworks)]
+#![test_runner(crate::testing::runner)]
+#![reexport_test_harness_main = "test_main"]
 
-use core::arch::global_asm;
+use core::arch::{asm, global_asm};
 use core::panic::PanicInfo;
 
 extern crate alloc;
-use alloc::vec::Vec;
 
+#[cfg_attr(not(feature = "apic"), allow(dead_code))]
+mod acpi;
 mod allocator;
+mod cmdline;
+mod dmesg;
+mod frame;
 mod gdt;
 mod interrupt;
 mod io;
+mod log;
 mod multiboot;
+mod paging;
+#[cfg(test)]
+mod testing;
+mod time;
 mod utils;
 
 global_asm!(include_str!("boot.s"), options(att_syntax));
 
 #[global_allocator]
-static ALLOC: utils::mutex::SpinMutex<allocator::BumpAlloc> =
-    utils::mutex::SpinMutex::new(allocator::BumpAlloc::new());
+static ALLOC: utils::mutex::SpinMutex<allocator::KernelHeap> =
+    utils::mutex::SpinMutex::new(allocator::new_kernel_heap());
 
+static FRAMES: utils::mutex::SpinMutex<frame::BitmapFrameAllocator> =
+    utils::mutex::SpinMutex::new(frame::BitmapFrameAllocator::new());
+
+static BUDDY: utils::mutex::SpinMutex<allocator::buddy::BuddyAllocator> =
+    utils::mutex::SpinMutex::new(allocator::buddy::BuddyAllocator::new());
+
+#[cfg(not(test))]
 #[panic_handler]
 fn panic_handler(info: &PanicInfo) -> ! {
     println!("{}", info);
+
+    let mut trace = [0; 8];
+    let depth = utils::stack::return_addresses(&mut trace);
+    print!("backtrace:");
+    for addr in &trace[..depth] {
+        print!(" {:#x}", addr);
+    }
+    println!();
+
     loop {}
 }
 
+#[cfg(test)]
+#[panic_handler]
+fn panic_handler(info: &PanicInfo) -> ! {
+    testing::panic(info)
+}
+
+// timer interrupts per second, the resolution of sleeps and timers
+const TIMER_FREQUENCY: u32 = 1000;
+
 static PORT_MANAGER: utils::mutex::SpinMutex<io::ports::PortAllocator> =
     utils::mutex::SpinMutex::new(io::ports::PortAllocator::new());
 
+/// Entry point called from `_start` in boot.s.
+///
+/// # Safety
+/// `info` must point to the multiboot info structure handed over by the
+/// bootloader.
 #[unsafe(no_mangle)]
 pub unsafe extern "C" fn kernel_main(magic: u32, info: *const multiboot::BootInfo) -> ! {
     io::vga::init_writer();
     if magic != 0x2badb002 {
         panic!("Not booted from multiboot")
     }
+    // before anything can allocate the memory it's in
+    cmdline::init(unsafe { (*info).cmdline() }.unwrap_or(""));
+    log::init();
 
     unsafe {
         (*info).print_mmap_entries();
-        ALLOC.lock().init(info);
+        FRAMES.lock().init(info);
     }
+    paging::init();
+    allocator::init_heap(&ALLOC);
+    BUDDY.lock().init(&mut FRAMES.lock());
     println!("hi");
 
     gdt::init_gdt();
     interrupt::init_idt(&mut PORT_MANAGER.lock());
+    #[cfg(feature = "apic")]
+    if let Err(e) = interrupt::apic::init() {
+        warn!("apic: {}, staying on the 8259s", e);
+    }
+    if let Err(e) = io::serial::init(&mut PORT_MANAGER.lock(), io::serial::ComPort::Com1, 115_200) {
+        warn!("serial: COM1: {}", e);
+    } else {
+        // catch the terminal up on everything printed so far
+        let _ = dmesg::replay(&mut io::serial::SerialWriter(io::serial::ComPort::Com1));
+        log::register_sink(&log::SERIAL_SINK, log::Level::Debug).expect("sink slot is free");
+    }
+    io::pit::init(&mut PORT_MANAGER.lock(), TIMER_FREQUENCY).expect("PIT setup");
+    time::init();
+    io::rtc::init(&mut PORT_MANAGER.lock()).expect("RTC setup");
+    info!("time: {}", io::rtc::now());
+    let ps2 = io::ps2::init(&mut PORT_MANAGER.lock());
+    match ps2 {
+        Ok(()) => {
+            if io::ps2::is_working(io::ps2::Channel::First) {
+                io::keyboard::init().expect("keyboard setup");
+            } else {
+                warn!("keyboard: no device on PS/2 port 1");
+            }
+            if let Err(e) = io::mouse::init() {
+                warn!("mouse: {}", e);
+            }
+        }
+        Err(e) => error!("ps2: {}", e),
+    }
+    allocator::dump_stats();
+    #[cfg(feature = "debug-alloc")]
+    allocator::dump_live_allocations();
 
-    // stub: enable ps2 & interrupts
-    let pm = &mut PORT_MANAGER.lock();
-    let mut ps2_status = pm.allocate(0x64).expect("ps2 status");
-    let mut ps2_data = pm.allocate(0x60).expect("ps2 data");
-    ps2_status.outb(0xAE);
-    ps2_status.outb(0x20);
-    let s = ps2_data.inb() | 0x01;
-    ps2_status.outb(0x60);
-    ps2_data.outb(s);
-
-    println!("ps2 init enable");
+    #[cfg(test)]
+    test_main();
 
-    #[allow(clippy::empty_loop)]
-    loop {}
+    loop {
+        while let Some(ch) = io::keyboard::read_char() {
+            print!("{}", ch);
+        }
+        unsafe { asm!("hlt", options(nomem, nostack)) };
+    }
 }
@@ -6,6 +6,7 @@
 
 #![allow(dead_code)]
 
+use crate::paging::phys_to_virt;
 use crate::println;
 
 #[repr(C, packed)]
@@ -60,16 +61,38 @@ pub struct MmapEntry {
     pub type_: u32, // name conflicts with rust keyword
 }
 
+// flags bit saying `cmdline` is valid
+const INFO_CMDLINE: u32 = 1 << 2;
+
 impl BootInfo {
+    /// The kernel command line, if the bootloader passed one.
+    ///
+    /// Only valid while the boot window is still mapped and before the
+    /// frame allocator can hand out the memory it lives in.
+    pub unsafe fn cmdline(&self) -> Option<&str> {
+        if self.flags & INFO_CMDLINE == 0 || self.cmdline == 0 {
+            return None;
+        }
+
+        let ptr = phys_to_virt(self.cmdline as usize) as *const core::ffi::c_char;
+        unsafe { core::ffi::CStr::from_ptr(ptr) }.to_str().ok()
+    }
+
     pub unsafe fn get_mmap_entries(&self) -> &[MmapEntry] {
         unsafe {
             core::slice::from_raw_parts(
-                self.mmap_addr as *const MmapEntry,
+                phys_to_virt(self.mmap_addr as usize) as *const MmapEntry,
                 self.mmap_length as usize / core::mem::size_of::<MmapEntry>(),
             )
         }
     }
 
+    /// Physical address range occupied by the memory map buffer itself.
+    pub fn mmap_range(&self) -> core::ops::Range<usize> {
+        let start = self.mmap_addr as usize;
+        start..start + self.mmap_length as usize
+    }
+
     #[allow(clippy::cast_precision_loss)]
     pub unsafe fn print_mmap_entries(&self) {
         println!("----- multiboot mmap -----");
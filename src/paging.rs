@@ -0,0 +1,370 @@
+// Two-level i686 paging: a page directory of 1024 entries, each pointing to
+// a page table of 1024 entries, each mapping a 4 KiB page.
+//
+// The last directory entry points back at the directory itself, so every
+// page table is reachable at a fixed virtual address without having to map
+// it anywhere:
+//   - page table `i` lives at 0xffc0_0000 + i * 4 KiB
+//   - the page directory lives at 0xffff_f000
+//
+// Paging is already on by the time Rust code runs: the trampoline in boot.s
+// maps the first 4 MiB of physical memory at KERNEL_OFFSET and sets up the
+// recursive entry. `init` then tightens that boot mapping down to the low
+// 1 MiB (VGA buffer, BIOS areas) and the kernel image.
+
+use crate::frame::BitmapFrameAllocator;
+use crate::info;
+
+use core::arch::asm;
+use core::ops::{BitOr, Range};
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+unsafe extern "C" {
+    static TEXT_START: u32;
+    static RODATA_END: u32;
+    static KERNEL_END: u32;
+    #[link_name = "stack_guard"]
+    static STACK_GUARD: u32;
+}
+
+/// Virtual address the first 4 MiB of physical memory are mapped at.
+pub const KERNEL_OFFSET: usize = 0xc000_0000;
+
+// end of the low memory window that stays mapped at KERNEL_OFFSET
+const LOW_MEMORY_END: usize = 0x10_0000;
+const BOOT_MAPPING_END: usize = 0x40_0000;
+
+pub const PAGE_SIZE: usize = 4096;
+const ENTRIES: usize = 1024;
+const RECURSIVE_INDEX: usize = ENTRIES - 1;
+
+const RECURSIVE_DIRECTORY: *mut u32 = 0xffff_f000 as *mut u32;
+const RECURSIVE_TABLES: *mut u32 = 0xffc0_0000 as *mut u32;
+
+const ADDRESS_MASK: u32 = 0xffff_f000;
+
+static DIRECTORY_PHYS: AtomicUsize = AtomicUsize::new(0);
+
+// window for mapping device memory and firmware tables, see map_physical
+const PHYSICAL_WINDOW_START: usize = 0xf000_0000;
+const PHYSICAL_WINDOW_END: usize = 0xffc0_0000; // page tables start here
+static PHYSICAL_WINDOW_NEXT: AtomicUsize = AtomicUsize::new(PHYSICAL_WINDOW_START);
+
+/// Flags for page directory and page table entries.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct PageFlags(u32);
+
+#[allow(dead_code)]
+impl PageFlags {
+    pub const PRESENT: Self = Self(1 << 0);
+    pub const WRITABLE: Self = Self(1 << 1);
+    pub const USER: Self = Self(1 << 2);
+    pub const WRITE_THROUGH: Self = Self(1 << 3);
+    pub const NO_CACHE: Self = Self(1 << 4);
+
+    pub const fn empty() -> Self {
+        Self(0)
+    }
+
+    pub const fn bits(self) -> u32 {
+        self.0
+    }
+
+    pub const fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl BitOr for PageFlags {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+
+/// Translate a physical address in the boot window to its virtual address.
+///
+/// Only the low 1 MiB and the kernel image stay mapped after `init`, before
+/// that this is valid for the whole first 4 MiB.
+pub const fn phys_to_virt(phys: usize) -> usize {
+    phys + KERNEL_OFFSET
+}
+
+/// Inverse of [`phys_to_virt`].
+pub const fn virt_to_phys(virt: usize) -> usize {
+    virt - KERNEL_OFFSET
+}
+
+#[inline]
+fn directory_index(virt: usize) -> usize {
+    virt >> 22
+}
+
+#[inline]
+fn table_index(virt: usize) -> usize {
+    (virt >> 12) & (ENTRIES - 1)
+}
+
+#[inline]
+fn directory() -> *mut u32 {
+    RECURSIVE_DIRECTORY
+}
+
+#[inline]
+unsafe fn table(index: usize) -> *mut u32 {
+    unsafe { RECURSIVE_TABLES.add(index * ENTRIES) }
+}
+
+#[inline]
+fn invalidate(virt: usize) {
+    unsafe {
+        asm!("invlpg ({})", in(reg) virt, options(att_syntax, nostack, preserves_flags));
+    }
+}
+
+/// Map the page containing `virt` to the frame containing `phys`.
+///
+/// A new page table is taken from `frames` if the directory entry covering
+/// `virt` is still empty.
+///
+/// # Safety
+/// Changing mappings underneath live references is undefined behaviour.
+pub unsafe fn map(
+    virt: usize,
+    phys: usize,
+    flags: PageFlags,
+    frames: &mut BitmapFrameAllocator,
+) -> Result<(), &'static str> {
+    let dir_index = directory_index(virt);
+    if dir_index == RECURSIVE_INDEX {
+        return Err("cannot map over the recursive page directory slot");
+    }
+
+    unsafe {
+        let pde = directory().add(dir_index);
+        if pde.read_volatile() & PageFlags::PRESENT.bits() == 0 {
+            let table_phys = frames.allocate().ok_or("out of frames for page table")?;
+            pde.write_volatile(
+                table_phys as u32
+                    | (PageFlags::PRESENT | PageFlags::WRITABLE | PageFlags::USER).bits(),
+            );
+
+            // flush whatever stale translation the recursive slot had
+            invalidate(table(dir_index) as usize);
+            core::ptr::write_bytes(table(dir_index), 0, ENTRIES);
+        }
+
+        let pte = table(dir_index).add(table_index(virt));
+        if pte.read_volatile() & PageFlags::PRESENT.bits() != 0 {
+            return Err("page already mapped");
+        }
+
+        pte.write_volatile((phys as u32 & ADDRESS_MASK) | (flags | PageFlags::PRESENT).bits());
+    }
+
+    invalidate(virt);
+    Ok(())
+}
+
+/// Remove the mapping for the page containing `virt`, returning the physical
+/// address of the frame it pointed at.
+///
+/// The frame itself is not freed.
+///
+/// # Safety
+/// Nothing may still reference memory in the unmapped page.
+#[allow(dead_code)]
+pub unsafe fn unmap(virt: usize) -> Option<usize> {
+    let dir_index = directory_index(virt);
+
+    let phys;
+    unsafe {
+        if directory().add(dir_index).read_volatile() & PageFlags::PRESENT.bits() == 0 {
+            return None;
+        }
+
+        let pte = table(dir_index).add(table_index(virt));
+        let entry = pte.read_volatile();
+        if entry & PageFlags::PRESENT.bits() == 0 {
+            return None;
+        }
+
+        phys = (entry & ADDRESS_MASK) as usize;
+        pte.write_volatile(0);
+    }
+
+    invalidate(virt);
+    Some(phys)
+}
+
+/// Replace the flags of the page containing `virt`, keeping the frame it
+/// points at.
+///
+/// The entry is rewritten in place, so unlike `unmap` followed by `map` the
+/// page never goes missing, which matters for the page running this code.
+///
+/// # Safety
+/// Removing access underneath live references is undefined behaviour.
+pub unsafe fn set_flags(virt: usize, flags: PageFlags) -> Result<(), &'static str> {
+    let dir_index = directory_index(virt);
+
+    unsafe {
+        if directory().add(dir_index).read_volatile() & PageFlags::PRESENT.bits() == 0 {
+            return Err("page not mapped");
+        }
+
+        let pte = table(dir_index).add(table_index(virt));
+        let entry = pte.read_volatile();
+        if entry & PageFlags::PRESENT.bits() == 0 {
+            return Err("page not mapped");
+        }
+
+        pte.write_volatile((entry & ADDRESS_MASK) | (flags | PageFlags::PRESENT).bits());
+    }
+
+    invalidate(virt);
+    Ok(())
+}
+
+/// Make `size` bytes of physical memory at `phys` accessible and return the
+/// virtual address `phys` ended up at.
+///
+/// For memory that doesn't belong to the frame allocator, like device
+/// registers and ACPI tables. The low 1 MiB is mapped already, everything
+/// else is mapped into a window that is not given back, see
+/// `unmap_physical`, so mappings are meant to be made once and kept.
+pub fn map_physical(
+    phys: usize,
+    size: usize,
+    flags: PageFlags,
+    frames: &mut BitmapFrameAllocator,
+) -> Result<usize, &'static str> {
+    if phys + size <= LOW_MEMORY_END {
+        return Ok(phys_to_virt(phys));
+    }
+
+    let offset = phys & (PAGE_SIZE - 1);
+    let len = (offset + size).next_multiple_of(PAGE_SIZE);
+    let start = PHYSICAL_WINDOW_NEXT
+        .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |next| {
+            (next + len <= PHYSICAL_WINDOW_END).then_some(next + len)
+        })
+        .map_err(|_| "physical mapping window is full")?;
+
+    let base = phys - offset;
+    for page in (0..len).step_by(PAGE_SIZE) {
+        if let Err(e) = unsafe { map(start + page, base + page, flags, frames) } {
+            unsafe { release_window(start, page, len) };
+            return Err(e);
+        }
+    }
+
+    Ok(start + offset)
+}
+
+// Unmap the first `mapped` bytes of the `len` bytes of window at `start`,
+// and hand the space back if nothing was reserved after it.
+unsafe fn release_window(start: usize, mapped: usize, len: usize) {
+    for page in (start..start + mapped).step_by(PAGE_SIZE) {
+        unsafe { unmap(page) };
+    }
+
+    let _ = PHYSICAL_WINDOW_NEXT.compare_exchange(
+        start + len,
+        start,
+        Ordering::Relaxed,
+        Ordering::Relaxed,
+    );
+}
+
+/// Remove a mapping made by `map_physical`, given the address and size it
+/// was made with.
+///
+/// The window space only becomes free again if this was the latest
+/// mapping, which is enough for taking a short look at something before
+/// mapping the rest of it.
+///
+/// # Safety
+/// Nothing may still reference memory in the unmapped range.
+pub unsafe fn unmap_physical(virt: usize, size: usize) {
+    // low memory stays mapped
+    if !(PHYSICAL_WINDOW_START..PHYSICAL_WINDOW_END).contains(&virt) {
+        return;
+    }
+
+    let offset = virt & (PAGE_SIZE - 1);
+    let len = (offset + size).next_multiple_of(PAGE_SIZE);
+    unsafe { release_window(virt - offset, len, len) };
+}
+
+/// Look up the physical address `virt` is mapped to.
+#[allow(dead_code)]
+pub fn translate(virt: usize) -> Option<usize> {
+    let dir_index = directory_index(virt);
+
+    unsafe {
+        if directory().add(dir_index).read_volatile() & PageFlags::PRESENT.bits() == 0 {
+            return None;
+        }
+
+        let entry = table(dir_index).add(table_index(virt)).read_volatile();
+        if entry & PageFlags::PRESENT.bits() == 0 {
+            return None;
+        }
+
+        Some((entry & ADDRESS_MASK) as usize | (virt & (PAGE_SIZE - 1)))
+    }
+}
+
+/// The unmapped page right below the boot stack.
+pub fn stack_guard() -> Range<usize> {
+    let start = unsafe { &STACK_GUARD as *const u32 as usize };
+    start..start + PAGE_SIZE
+}
+
+/// Take over the page directory built in boot.s.
+///
+/// Kernel code and read-only data lose write access, the page below the
+/// boot stack is unmapped so an overflow faults instead of silently
+/// overwriting whatever precedes the stack in .bss, and the part of the boot
+/// mapping above the kernel image is dropped so that those frames are only
+/// reachable through explicit mappings.
+pub fn init() {
+    let dir_phys: usize;
+    unsafe {
+        asm!("mov %cr3, {}", out(reg) dir_phys, options(att_syntax, nomem, nostack));
+    }
+    DIRECTORY_PHYS.store(dir_phys, Ordering::Relaxed);
+
+    let (read_only_start, read_only_end, kend) = unsafe {
+        (
+            &TEXT_START as *const u32 as usize,
+            &RODATA_END as *const u32 as usize,
+            &KERNEL_END as *const u32 as usize,
+        )
+    };
+
+    let mut page = read_only_start;
+    while page < read_only_end {
+        unsafe { set_flags(page, PageFlags::empty()).expect("kernel section is mapped") };
+        page += PAGE_SIZE;
+    }
+
+    unsafe { unmap(stack_guard().start).expect("stack guard is mapped") };
+
+    let mut page = kend.next_multiple_of(PAGE_SIZE);
+    while page < phys_to_virt(BOOT_MAPPING_END) {
+        unsafe { unmap(page) };
+        page += PAGE_SIZE;
+    }
+
+    info!(
+        "kernel at {:#x}..{:#x}, low memory at {:#x}..{:#x}, directory at {:#x}",
+        read_only_start,
+        kend,
+        KERNEL_OFFSET,
+        phys_to_virt(LOW_MEMORY_END),
+        dir_phys
+    );
+}
@@ -0,0 +1,88 @@
+// Test runner for `cargo test`.
+//
+// The test kernel boots like the normal one and then runs every
+// `#[test_case]` function. Output goes over the serial port like all other
+// prints. When done, or when a test panics or runs too long, it shuts QEMU
+// down through the isa-debug-exit device, whose exit code tells the runner
+// script whether the tests passed.
+//
+// Running too long is noticed by a PIT timer, so a test that hangs with
+// interrupts off is only stopped by QEMU_TIMEOUT in scripts/qemu-run.sh.
+
+use crate::io::pit;
+use crate::io::ports::lockfree_outb;
+use crate::utils::stack::return_addresses;
+use crate::{print, println};
+
+use core::arch::asm;
+use core::panic::PanicInfo;
+
+// where the runner script puts the isa-debug-exit device
+const EXIT_PORT: u16 = 0xf4;
+
+// generous, the timer only fires this late if a test hangs
+const TEST_TIMEOUT_MS: u64 = 10_000;
+
+/// Written to the isa-debug-exit device, which makes QEMU exit with
+/// `(code << 1) | 1`. Both differ from what QEMU exits with on its own.
+#[derive(Clone, Copy)]
+#[repr(u8)]
+pub enum QemuExitCode {
+    Success = 0x10,
+    Failed = 0x11,
+}
+
+pub fn exit_qemu(code: QemuExitCode) -> ! {
+    unsafe { lockfree_outb(EXIT_PORT, code as u8) };
+
+    // only reached without the device, e.g. on real hardware
+    loop {
+        unsafe { asm!("cli", "hlt", options(nomem, nostack)) };
+    }
+}
+
+pub trait Testable {
+    fn run(&self);
+}
+
+impl<T: Fn()> Testable for T {
+    fn run(&self) {
+        print!("{} ... ", core::any::type_name::<T>());
+        let timer = pit::set_timer(TEST_TIMEOUT_MS, timed_out).expect("timer slot for the test");
+        self();
+        pit::cancel_timer(timer);
+        println!("ok");
+    }
+}
+
+// Runs from the timer interrupt. The output locks are only ever held with
+// interrupts off, so the hung test can't be holding them.
+fn timed_out() {
+    println!("timed out after {} ms", TEST_TIMEOUT_MS);
+    exit_qemu(QemuExitCode::Failed);
+}
+
+pub fn runner(tests: &[&dyn Testable]) {
+    println!("running {} tests", tests.len());
+    for test in tests {
+        test.run();
+    }
+    println!("all tests passed");
+    exit_qemu(QemuExitCode::Success);
+}
+
+/// A panic is a failed test.
+pub fn panic(info: &PanicInfo) -> ! {
+    println!("FAILED");
+    println!("{}", info);
+
+    let mut trace = [0; 8];
+    let depth = return_addresses(&mut trace);
+    print!("backtrace:");
+    for addr in &trace[..depth] {
+        print!(" {:#x}", addr);
+    }
+    println!();
+
+    exit_qemu(QemuExitCode::Failed);
+}
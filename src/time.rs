@@ -0,0 +1,183 @@
+// Clocksources: where "nanoseconds since boot" comes from.
+//
+// The PIT tick counter always works but only has the resolution of one
+// tick. The CPU's time stamp counter counts cycles, and once its frequency
+// is known it is by far the most precise and cheapest clock there is.
+// `init` calibrates it against PIT channel 2 and, if that works, makes it
+// the clocksource everything else should use.
+//
+// The TSC starts counting at reset and the PIT when it is programmed, both
+// close enough to boot for logs and profiling.
+
+use crate::io::pit;
+use crate::{info, warn};
+
+use core::arch::asm;
+use core::arch::x86::{__cpuid, _rdtsc};
+use core::sync::atomic::{AtomicBool, Ordering};
+
+const NANOS_PER_SEC: u64 = 1_000_000_000;
+
+// longer is more precise, but stalls boot
+const CALIBRATION_MS: u32 = 50;
+
+// CPUID.1:EDX
+const CPUID_TSC: u32 = 1 << 4;
+// CPUID.80000007H:EDX, the TSC ticks at a constant rate in all power states
+const CPUID_INVARIANT_TSC: u32 = 1 << 8;
+
+pub trait Clocksource {
+    fn name(&self) -> &'static str;
+
+    /// Nanoseconds since boot.
+    fn nanos(&self) -> u64;
+
+    /// Smallest step `nanos` moves by.
+    fn resolution_ns(&self) -> u64;
+}
+
+/// The PIT's tick counter.
+pub struct PitClock;
+
+impl Clocksource for PitClock {
+    fn name(&self) -> &'static str {
+        "pit"
+    }
+
+    fn nanos(&self) -> u64 {
+        ticks_to_nanos(pit::ticks(), u64::from(pit::frequency()))
+    }
+
+    fn resolution_ns(&self) -> u64 {
+        NANOS_PER_SEC / u64::from(pit::frequency())
+    }
+}
+
+/// The time stamp counter, at its calibrated frequency.
+pub struct TscClock {
+    hz: u64,
+}
+
+impl Clocksource for TscClock {
+    fn name(&self) -> &'static str {
+        "tsc"
+    }
+
+    fn nanos(&self) -> u64 {
+        ticks_to_nanos(read_tsc(), self.hz)
+    }
+
+    fn resolution_ns(&self) -> u64 {
+        (NANOS_PER_SEC / self.hz).max(1)
+    }
+}
+
+// split to keep the multiplication from overflowing
+fn ticks_to_nanos(ticks: u64, hz: u64) -> u64 {
+    ticks / hz * NANOS_PER_SEC + ticks % hz * NANOS_PER_SEC / hz
+}
+
+pub fn read_tsc() -> u64 {
+    unsafe { _rdtsc() }
+}
+
+/// CPUID exists if the ID flag in EFLAGS can be flipped.
+pub fn has_cpuid() -> bool {
+    const ID: u32 = 1 << 21;
+    let (before, after): (u32, u32);
+    unsafe {
+        asm!(
+            "pushf",
+            "pop {before}",
+            "mov {before}, {after}",
+            "xor ${id}, {after}",
+            "push {after}",
+            "popf",
+            "pushf",
+            "pop {after}",
+            "push {before}",
+            "popf",
+            before = out(reg) before,
+            after = out(reg) after,
+            id = const ID,
+            options(att_syntax)
+        );
+    }
+    (before ^ after) & ID != 0
+}
+
+fn has_tsc() -> bool {
+    has_cpuid() && unsafe { __cpuid(1) }.edx & CPUID_TSC != 0
+}
+
+fn has_invariant_tsc() -> bool {
+    let max_extended = unsafe { __cpuid(0x8000_0000) }.eax;
+    max_extended >= 0x8000_0007 && unsafe { __cpuid(0x8000_0007) }.edx & CPUID_INVARIANT_TSC != 0
+}
+
+static PIT_CLOCK: PitClock = PitClock;
+
+// written once by init before USE_TSC is set, read only after
+static mut TSC_CLOCK: TscClock = TscClock { hz: 0 };
+static USE_TSC: AtomicBool = AtomicBool::new(false);
+// the PIT clock can't be read before the PIT is set up
+static READY: AtomicBool = AtomicBool::new(false);
+
+/// Measure the TSC frequency against the PIT.
+pub fn calibrate_tsc() -> Result<u64, &'static str> {
+    if !has_tsc() {
+        return Err("no TSC");
+    }
+
+    let cycles = pit::measure(CALIBRATION_MS, read_tsc)?;
+    // some emulators have a TSC that never moves
+    if cycles == 0 {
+        return Err("tsc not counting");
+    }
+    Ok(cycles * 1000 / u64::from(CALIBRATION_MS))
+}
+
+/// Pick the best clocksource. Needs the PIT to be set up.
+pub fn init() {
+    match calibrate_tsc() {
+        Ok(hz) => {
+            unsafe { (&raw mut TSC_CLOCK).write(TscClock { hz }) };
+            USE_TSC.store(true, Ordering::Release);
+            info!(
+                "tsc at {} MHz{}",
+                hz / 1_000_000,
+                if has_invariant_tsc() {
+                    ", invariant"
+                } else {
+                    ""
+                }
+            );
+        }
+        Err(e) => warn!("tsc unusable, {}", e),
+    }
+
+    READY.store(true, Ordering::Release);
+    let clock = clocksource();
+    info!(
+        "clocksource {}, {} ns resolution",
+        clock.name(),
+        clock.resolution_ns()
+    );
+}
+
+/// The most precise clocksource available.
+pub fn clocksource() -> &'static dyn Clocksource {
+    if USE_TSC.load(Ordering::Acquire) {
+        unsafe { (&raw const TSC_CLOCK).as_ref() }.expect("tsc clock is set")
+    } else {
+        &PIT_CLOCK
+    }
+}
+
+/// Nanoseconds since boot, from the best clocksource. 0 until `init`.
+pub fn nanos_since_boot() -> u64 {
+    if !READY.load(Ordering::Acquire) {
+        return 0;
+    }
+    clocksource().nanos()
+}
@@ -1,7 +1,7 @@
 pub trait CanManipulateBits {
     fn create_mask(shift_left: Self, len: Self) -> Self;
     fn get_bits(&self, shift_left: Self, len: Self) -> Self;
-    fn set_bits(&self, shift_left: Self, val: Self, len: Self) -> Self;
+    fn set_bits(&self, shift_left: Self, len: Self, val: Self) -> Self;
     fn set_one_bit(&self, shift_left: Self, enable: bool) -> Self;
 }
 
@@ -26,7 +26,7 @@ macro_rules! impl_bit_manipulation {
 
             #[inline]
             fn set_one_bit(&self, shift_left: Self, enable: bool) -> Self {
-                self.set_bits(shift_left, enable as Self, 1)
+                self.set_bits(shift_left, 1, enable as Self)
             }
         }
     };
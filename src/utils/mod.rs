@@ -1,2 +1,4 @@
 pub mod bits;
 pub mod mutex;
+pub mod spsc;
+pub mod stack;
@@ -45,6 +45,15 @@ impl<T> SpinMutex<T> {
 
         SpinMutexGuard { mutex: self }
     }
+
+    /// Release the lock without a guard.
+    ///
+    /// # Safety
+    /// Only for when the holder can never run again, e.g. when reporting a
+    /// fatal error that interrupted it.
+    pub unsafe fn force_unlock(&self) {
+        self.lock.store(false, Ordering::Release);
+    }
 }
 
 impl<T> Deref for SpinMutexGuard<'_, T> {
@@ -0,0 +1,63 @@
+use core::cell::UnsafeCell;
+use core::mem::MaybeUninit;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// Fixed size ring buffer for one producer and one consumer, without locks.
+///
+/// Meant for handing data from an interrupt handler to normal code: the
+/// handler can't wait for a lock the code it interrupted holds. Only the
+/// producer moves `tail` and only the consumer moves `head`, so as long as
+/// there is only one of each, neither ever waits for the other.
+///
+/// One slot is kept empty to tell a full queue from an empty one.
+pub struct SpscQueue<T: Copy, const N: usize> {
+    buffer: UnsafeCell<[MaybeUninit<T>; N]>,
+    head: AtomicUsize, // next slot to read
+    tail: AtomicUsize, // next slot to write
+}
+
+// slots are only accessed by the side that owns them at that moment
+unsafe impl<T: Copy + Send, const N: usize> Sync for SpscQueue<T, N> {}
+
+impl<T: Copy, const N: usize> SpscQueue<T, N> {
+    pub const fn new() -> Self {
+        Self {
+            buffer: UnsafeCell::new([MaybeUninit::uninit(); N]),
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+        }
+    }
+
+    /// Add `value` at the back, or hand it back if the queue is full.
+    ///
+    /// Only one context may push.
+    pub fn push(&self, value: T) -> Result<(), T> {
+        let tail = self.tail.load(Ordering::Relaxed);
+        let next = (tail + 1) % N;
+        if next == self.head.load(Ordering::Acquire) {
+            return Err(value);
+        }
+
+        unsafe { (*self.buffer.get())[tail].write(value) };
+        self.tail.store(next, Ordering::Release);
+        Ok(())
+    }
+
+    /// Take the value at the front.
+    ///
+    /// Only one context may pop.
+    pub fn pop(&self) -> Option<T> {
+        let head = self.head.load(Ordering::Relaxed);
+        if head == self.tail.load(Ordering::Acquire) {
+            return None;
+        }
+
+        let value = unsafe { (*self.buffer.get())[head].assume_init() };
+        self.head.store((head + 1) % N, Ordering::Release);
+        Some(value)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.head.load(Ordering::Acquire) == self.tail.load(Ordering::Acquire)
+    }
+}
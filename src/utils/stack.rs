@@ -0,0 +1,31 @@
+use crate::paging::KERNEL_OFFSET;
+use core::arch::asm;
+
+/// Fill `out` with the return addresses found by walking the frame pointer
+/// chain, innermost first, and return how many were found.
+///
+/// The first entry is the return address into the caller of the function
+/// that calls this. The walk stops at the zeroed frame pointer set up in
+/// boot.s, or at anything that does not look like a kernel stack address.
+#[inline(always)]
+pub fn return_addresses(out: &mut [usize]) -> usize {
+    let mut frame: usize;
+    unsafe {
+        asm!("mov %ebp, {}", out(reg) frame, options(att_syntax, nomem, nostack, preserves_flags));
+    }
+
+    let mut count = 0;
+    while count < out.len() && frame >= KERNEL_OFFSET && frame % 4 == 0 {
+        // saved ebp at [ebp], return address at [ebp + 4]
+        let ret = unsafe { ((frame + 4) as *const usize).read() };
+        if ret == 0 {
+            break;
+        }
+
+        out[count] = ret;
+        count += 1;
+        frame = unsafe { (frame as *const usize).read() };
+    }
+
+    count
+}